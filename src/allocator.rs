@@ -0,0 +1,333 @@
+use client_id::ClientId;
+use rand;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+
+/// Sposób wyboru adresu z puli. Odpowiednio: kolejny po ostatnio przydzielonym, losowy,
+/// wyznaczony przez hash identyfikatora klienta - ten sam klient dostaje ten sam adres, o ile jest wolny.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    Sequential,
+    Random,
+    Hash,
+}
+
+/// Pula adresów jako mapa bitowa, bit ustawiony oznacza adres zajęty lub wykluczony.
+/// Druga mapa ma bit na każde pełne słowo pierwszej, więc wolny adres znajdujemy bez przeglądania zajętych po kolei.
+/// Pula może składać się z kilku przedziałów - bity kolejnych przedziałów leżą w mapie jeden za drugim.
+pub struct Allocator {
+    segments: Vec<Segment>,
+    size: u32,
+    used: Vec<u64>,
+    full: Vec<u64>,
+    excluded: Vec<u64>,
+    free: u32,
+    cursor: u32,
+    strategy: Strategy,
+}
+
+// Przedział puli: pierwszy adres, numer jego bitu w mapie, liczba adresów
+struct Segment {
+    start: u32,
+    offset: u32,
+    len: u32,
+}
+
+fn get_bit(bits: &[u64], i: u32) -> bool {
+    bits[(i / 64) as usize] & (1 << (i % 64)) != 0
+}
+
+fn set_bit(bits: &mut [u64], i: u32, value: bool) {
+    if value {
+        bits[(i / 64) as usize] |= 1 << (i % 64);
+    } else {
+        bits[(i / 64) as usize] &= !(1 << (i % 64));
+    }
+}
+
+impl Allocator {
+    /// Pula z przedziałów adresów. Nachodzące na siebie i sąsiednie przedziały są łączone.
+    pub fn new(ranges: &[Range<u32>], strategy: Strategy) -> Self {
+        let mut sorted: Vec<Range<u32>> = ranges.iter().filter(|range| range.start < range.end).cloned().collect();
+        sorted.sort_by_key(|range| range.start);
+
+        let mut merged: Vec<Range<u32>> = Vec::new();
+        for range in sorted {
+            if let Some(last) = merged.last_mut() {
+                if range.start <= last.end {
+                    last.end = last.end.max(range.end);
+                    continue;
+                }
+            }
+            merged.push(range);
+        }
+
+        let mut segments = Vec::new();
+        let mut size = 0;
+        for range in merged {
+            let len = range.end - range.start;
+            segments.push(Segment { start: range.start, offset: size, len: len });
+            size += len;
+        }
+
+        let words = ((size + 63) / 64) as usize;
+        let mut allocator = Allocator {
+            segments: segments,
+            size: size,
+            used: vec![0; words],
+            full: vec![0; (words + 63) / 64],
+            excluded: vec![0; words],
+            free: size,
+            cursor: 0,
+            strategy: strategy,
+        };
+
+        // Bity za końcem puli w ostatnim słowie są zawsze zajęte
+        if size % 64 != 0 {
+            let last = words - 1;
+            allocator.used[last] = !0 << (size % 64);
+        }
+
+        allocator
+    }
+
+    /// Numer bitu adresu, o ile adres należy do puli
+    fn index(&self, ip: u32) -> Option<u32> {
+        self.segments.iter()
+            .find(|segment| ip >= segment.start && ip - segment.start < segment.len)
+            .map(|segment| segment.offset + (ip - segment.start))
+    }
+
+    /// Adres odpowiadający numerowi bitu
+    fn address(&self, index: u32) -> u32 {
+        let segment = self.segments.iter()
+            .find(|segment| index >= segment.offset && index - segment.offset < segment.len)
+            .unwrap();
+        segment.start + (index - segment.offset)
+    }
+
+    /// Czy adres należy do puli
+    pub fn contains(&self, ip: u32) -> bool {
+        self.index(ip).is_some()
+    }
+
+    pub fn is_excluded(&self, ip: u32) -> bool {
+        self.index(ip).map_or(false, |index| get_bit(&self.excluded, index))
+    }
+
+    /// Liczba wolnych adresów
+    pub fn free_count(&self) -> u32 {
+        self.free
+    }
+
+    /// Wykluczenie adresu - nigdy nie zostanie przydzielony, `release` go nie zwalnia
+    pub fn exclude(&mut self, ip: u32) {
+        let index = match self.index(ip) {
+            Some(index) => index,
+            None => return,
+        };
+        if !get_bit(&self.used, index) {
+            self.mark(index, true);
+        }
+        set_bit(&mut self.excluded, index, true);
+    }
+
+    /// Wykluczenie przedziału adresów. Przeglądamy tylko jego część wspólną z pulą.
+    pub fn exclude_range(&mut self, range: &Range<u32>) {
+        let overlaps: Vec<Range<u32>> = self.segments.iter()
+            .map(|segment| range.start.max(segment.start) .. range.end.min(segment.start + segment.len))
+            .filter(|overlap| overlap.start < overlap.end)
+            .collect();
+
+        for ip in overlaps.into_iter().flat_map(|overlap| overlap) {
+            self.exclude(ip);
+        }
+    }
+
+    /// Oznaczenie adresu jako zajętego. False, jeśli był już zajęty lub jest poza pulą.
+    pub fn reserve(&mut self, ip: u32) -> bool {
+        match self.index(ip) {
+            Some(index) if !get_bit(&self.used, index) => {
+                self.mark(index, true);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Zwolnienie adresu. Adresy wykluczone i spoza puli pomijamy.
+    pub fn release(&mut self, ip: u32) {
+        match self.index(ip) {
+            Some(index) if get_bit(&self.used, index) && !get_bit(&self.excluded, index) => self.mark(index, false),
+            _ => (),
+        }
+    }
+
+    /// Wolny adres dla klienta według strategii, bez oznaczania go jako zajętego
+    pub fn next_free(&mut self, client: &ClientId) -> Option<u32> {
+        if self.free == 0 { return None; }
+
+        let from = match self.strategy {
+            Strategy::Sequential => self.cursor,
+            Strategy::Random => rand::random::<u32>() % self.size,
+            Strategy::Hash => {
+                let mut hasher = DefaultHasher::new();
+                client.hash(&mut hasher);
+                (hasher.finish() % self.size as u64) as u32
+            },
+        };
+
+        let index = self.find_free(from)?;
+        if self.strategy == Strategy::Sequential {
+            self.cursor = (index + 1) % self.size;
+        }
+        Some(self.address(index))
+    }
+
+    fn mark(&mut self, index: u32, used: bool) {
+        set_bit(&mut self.used, index, used);
+        let word = index / 64;
+        set_bit(&mut self.full, word, self.used[word as usize] == !0);
+        if used { self.free -= 1; } else { self.free += 1; }
+    }
+
+    /// Pierwszy wolny indeks od `from`, z zawinięciem na początek puli
+    fn find_free(&self, from: u32) -> Option<u32> {
+        let words = self.used.len() as u32;
+        let first_word = from / 64;
+
+        // Reszta słowa, od którego zaczynamy
+        let rest = !self.used[first_word as usize] & (!0u64 << (from % 64));
+        if rest != 0 {
+            return Some(first_word * 64 + rest.trailing_zeros());
+        }
+
+        // Kolejne niepełne słowa według drugiej mapy; na końcu początek pierwszego słowa
+        let word = self.next_non_full(first_word + 1, words).or_else(|| self.next_non_full(0, first_word + 1))?;
+        let free_bits = !self.used[word as usize];
+        Some(word * 64 + free_bits.trailing_zeros())
+    }
+
+    /// Pierwsze niepełne słowo z przedziału [from, to). Całe grupy 64 pełnych słów pomijamy jednym porównaniem.
+    fn next_non_full(&self, from: u32, to: u32) -> Option<u32> {
+        let mut word = from;
+        while word < to {
+            let group = word / 64;
+            let candidates = !self.full[group as usize] & (!0u64 << (word % 64));
+            if candidates != 0 {
+                let found = group * 64 + candidates.trailing_zeros();
+                return if found < to { Some(found) } else { None };
+            }
+            word = (group + 1) * 64;
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+fn test_client(i: u8) -> ClientId {
+    ClientId::Identifier(vec![i])
+}
+
+/// Kolejne adresy po kolei, z pominięciem zajętych i wykluczonych, z zawinięciem
+#[test]
+fn sequential_allocation_test() {
+    let mut allocator = Allocator::new(&[100..200], Strategy::Sequential);
+    allocator.exclude(101);
+    assert!(allocator.reserve(102));
+
+    assert_eq!(allocator.next_free(&test_client(1)), Some(100));
+    assert!(allocator.reserve(100));
+    assert_eq!(allocator.next_free(&test_client(1)), Some(103));
+
+    for ip in 103..200 {
+        assert!(allocator.reserve(ip));
+    }
+    assert_eq!(allocator.free_count(), 0);
+    assert_eq!(allocator.next_free(&test_client(1)), None);
+
+    allocator.release(101);
+    allocator.release(150);
+    assert_eq!(allocator.next_free(&test_client(1)), Some(150));
+    assert!(!allocator.reserve(1000));
+}
+
+/// Kilka przedziałów z wykluczeniami - przydział przechodzi z jednego przedziału do następnego
+#[test]
+fn multiple_ranges_test() {
+    let mut allocator = Allocator::new(&[300..303, 100..103, 102..105], Strategy::Sequential);
+    assert_eq!(allocator.free_count(), 8);
+    assert!(!allocator.contains(200));
+
+    allocator.exclude_range(&(103..301));
+    assert_eq!(allocator.free_count(), 5);
+    assert!(allocator.is_excluded(300));
+    assert!(!allocator.is_excluded(200));
+
+    let mut allocated = Vec::new();
+    while let Some(ip) = allocator.next_free(&test_client(1)) {
+        allocator.reserve(ip);
+        allocated.push(ip);
+    }
+    assert_eq!(allocated, vec![100, 101, 102, 301, 302]);
+
+    allocator.release(300);
+    assert_eq!(allocator.next_free(&test_client(1)), None);
+}
+
+/// Ten sam klient dostaje ten sam adres, gdy jest wolny
+#[test]
+fn hash_allocation_test() {
+    let mut allocator = Allocator::new(&[0..1000], Strategy::Hash);
+    let ip = allocator.next_free(&test_client(7)).unwrap();
+    assert_eq!(allocator.next_free(&test_client(7)), Some(ip));
+
+    allocator.reserve(ip);
+    let other = allocator.next_free(&test_client(7)).unwrap();
+    assert!(other != ip);
+    allocator.release(ip);
+    assert_eq!(allocator.next_free(&test_client(7)), Some(ip));
+}
+
+// Pula z Config.json, zajęta w 99% - wolny co setny adres
+#[cfg(test)]
+const BENCH_POOL: Range<u32> = 0x0A_00_00_0A .. 0x0A_00_C8_C9;
+
+/// Przydział i zwolnienie adresu w prawie pełnej puli - mapa bitowa
+#[cfg(test)]
+#[bench]
+fn bench_allocator(b: &mut ::test::Bencher) {
+    let mut allocator = Allocator::new(&[BENCH_POOL], Strategy::Sequential);
+    for ip in BENCH_POOL.filter(|ip| ip % 100 != 0) {
+        allocator.reserve(ip);
+    }
+    let client = test_client(1);
+
+    b.iter(|| {
+        let ip = allocator.next_free(&client).unwrap();
+        allocator.reserve(ip);
+        allocator.release(ip);
+    });
+}
+
+/// To samo dla dotychczasowego podejścia - Cycle<Range<u32>> i sprawdzanie mapy dzierżaw
+#[cfg(test)]
+#[bench]
+fn bench_cycle_iterator(b: &mut ::test::Bencher) {
+    use std::collections::HashMap;
+
+    let mut lease_map: HashMap<u32, ()> = BENCH_POOL.filter(|ip| ip % 100 != 0).map(|ip| (ip, ())).collect();
+    let mut pool_iter = BENCH_POOL.cycle();
+
+    b.iter(|| {
+        let mut ip = pool_iter.next().unwrap();
+        while lease_map.contains_key(&ip) {
+            ip = pool_iter.next().unwrap();
+        }
+        lease_map.insert(ip, ());
+        lease_map.remove(&ip);
+    });
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+use std::str::FromStr;
+use hw_address::{HardwareAddress, ParseHardwareAddressError};
+
+/// Tożsamość klienta według RFC 2131: identyfikator klienta (opcja 61), a gdy klient go nie wysyła - typ łącza i chaddr.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum ClientId {
+    Identifier(Vec<u8>),
+    Hardware(HardwareAddress),
+}
+
+/// Identyfikator jako id/01:02:..., adres sprzętowy jak w `HardwareAddress`
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientId::Identifier(ref id) => {
+                let octets: Vec<String> = id.iter().map(|b| format!("{:02X}", b)).collect();
+                write!(f, "id/{}", octets.join(":"))
+            },
+            ClientId::Hardware(ref hwaddr) => write!(f, "{}", hwaddr),
+        }
+    }
+}
+
+impl fmt::Debug for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for ClientId {
+    type Err = ParseHardwareAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("id/") {
+            let id = s[3..].split(|c| c == ':' || c == '-')
+                .map(|octet| u8::from_str_radix(octet, 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| ParseHardwareAddressError)?;
+            if id.is_empty() { return Err(ParseHardwareAddressError); }
+            return Ok(ClientId::Identifier(id));
+        }
+
+        s.parse().map(ClientId::Hardware)
+    }
+}
+
+
+/// Rezerwacje w konfiguracji po identyfikatorze klienta lub adresie sprzętowym
+#[test]
+fn parse_client_id_test() {
+    let id: ClientId = "id/01:08:00:27:73:42:4E".parse().unwrap();
+    assert_eq!(id, ClientId::Identifier(vec![0x01, 0x08, 0x00, 0x27, 0x73, 0x42, 0x4E]));
+    assert_eq!(id.to_string(), "id/01:08:00:27:73:42:4E");
+
+    let hwaddr: ClientId = "08:00:27:73:42:4E".parse().unwrap();
+    assert_eq!(hwaddr, ClientId::Hardware("08:00:27:73:42:4E".parse().unwrap()));
+
+    // Klient z hlen 0 wraca z magazynu dzierżaw pod tym samym identyfikatorem
+    let empty = ClientId::Hardware(HardwareAddress::new(32, &[]));
+    assert_eq!(empty.to_string().parse::<ClientId>().unwrap(), empty);
+}
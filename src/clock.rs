@@ -0,0 +1,47 @@
+use std::time::SystemTime;
+#[cfg(test)]
+use std::cell::Cell;
+#[cfg(test)]
+use std::rc::Rc;
+#[cfg(test)]
+use std::time::Duration;
+
+/// Źródło bieżącego czasu dla serwera. Wszystkie czasy dzierżaw liczymy od niego, więc w testach
+/// można przesuwać czas ręcznie zamiast czekać.
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+/// Zegar systemowy
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Zegar przesuwany ręcznie. Kopie dzielą ten sam czas - jedną dostaje serwer, drugą przesuwa test.
+#[cfg(test)]
+#[derive(Clone)]
+pub struct ManualClock {
+    now: Rc<Cell<SystemTime>>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new(start: SystemTime) -> Self {
+        ManualClock { now: Rc::new(Cell::new(start)) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        self.now.get()
+    }
+}
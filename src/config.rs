@@ -4,13 +4,25 @@ use std::net::Ipv4Addr;
 use serde_json;
 use byteorder::{ReadBytesExt, NetworkEndian};
 use std::io::Cursor;
-use hwaddr::HwAddr;
+use client_id::ClientId;
+use iface::interface_ipv4;
+use allocator::Strategy;
+use lease_store::Backend;
 
-// Surowa konfiguracja zebrana z JSONA
+// Przedział adresów w konfiguracji, oba końce włącznie
+#[derive(Serialize, Deserialize)]
+struct RawRange {
+    start: String,
+    end: String,
+}
+
+// Surowa konfiguracja zebrana z JSONA. Pulę można podać jako pool_start i pool_end, jako listę przedziałów, albo obie naraz.
 #[derive(Serialize, Deserialize)]
 struct RawConfig {
-    pool_start: String,
-    pool_end: String,
+    pool_start: Option<String>,
+    pool_end: Option<String>,
+    pool_ranges: Option<Vec<RawRange>>,
+    exclusions: Option<Vec<RawRange>>,
     pool_mask: String,
     dns: Vec<String>,
     gateway: String,
@@ -18,19 +30,56 @@ struct RawConfig {
     lease_time: u32,
     expiration_time: u32,
     iface: String,
+    server_name: Option<String>,
+    boot_file: Option<String>,
+    bootp_pool_start: Option<String>,
+    bootp_pool_end: Option<String>,
+    server_id: Option<String>,
+    next_server: Option<String>,
+    reclaim_oldest_expired: Option<bool>,
+    allocation_strategy: Option<Strategy>,
+    offer_time: Option<u32>,
+    lease_file: Option<String>,
+    lease_store: Option<Backend>,
+    history_size: Option<usize>,
+    renewal_ratio: Option<f64>,
+    rebinding_ratio: Option<f64>,
+    renewal_jitter: Option<f64>,
 }
 
-// Konfiguracja, która trafi do aktorów. Odpowiednio: pula adresów, maska, dnsy, brama i adres serwera DHCP, statyczne przydziały, czas dzierżawy, czas na który trzymamy adres po wygaśnięciu, interfejs gniazda
+// Konfiguracja, która trafi do aktorów. Odpowiednio: przedziały puli adresów, przedziały wyłączone z puli, maska, dnsy, brama i adres serwera DHCP, statyczne przydziały, czas dzierżawy, czas na który trzymamy adres po wygaśnięciu, interfejs gniazda,
+// opcjonalne pola sname i file odpowiedzi (start sieciowy), opcjonalna pula dla klientów BOOTP,
+// identyfikator serwera DHCP (opcja 54) i adres następnego serwera (siaddr),
+// polityka odzyskiwania adresów: wygasłe dzierżawy zabieramy dopiero przy pełnej puli, najstarsze najpierw (bez niej czekają do końca okresu po wygaśnięciu),
+// sposób wyboru adresu z puli, czas, na który trzymamy adres zaoferowany w DHCPOFFER,
+// magazyn dzierżaw i jego plik - dziennik JSON lub baza SQLite; w pamięci dzierżawy giną przy restarcie,
+// liczba klientów, których ostatni adres pamiętamy - najdawniej obsłużonych zapominamy,
+// T1 i T2 jako ułamki czasu dzierżawy oraz rozrzut T1 między klientami, też jako ułamek czasu dzierżawy (0 - bez rozrzutu)
 #[derive(Clone)]
 pub struct Config {
-    pub pool_range: Range<u32>,
+    pub pool_ranges: Vec<Range<u32>>,
+    pub exclusions: Vec<Range<u32>>,
     pub pool_mask: u32,
     pub dns: Vec<u32>,
     pub gateway: u32,
-    pub statics: HashMap<u32, u64>,
+    pub statics: HashMap<u32, ClientId>,
     pub lease_time: u32,
     pub expiration_time: u32,
     pub interface: String,
+    pub server_name: Option<String>,
+    pub boot_file: Option<String>,
+    pub bootp_range: Option<Range<u32>>,
+    pub server_id: u32,
+    pub next_server: u32,
+    pub reclaim_oldest_expired: bool,
+    pub allocation_strategy: Strategy,
+    pub offer_time: u32,
+    pub lease_file: Option<String>,
+    pub lease_store: Backend,
+    pub history_size: usize,
+    pub renewal_ratio: f64,
+    pub rebinding_ratio: f64,
+    pub renewal_jitter: f64,
 }
 
 // Ip w formacie 192.168.0.1 na liczbę całkowitą
@@ -40,29 +89,92 @@ fn get_ip(text: &String) -> u32 {
     octets.read_u32::<NetworkEndian>().unwrap()
 }
 
+// Przedział z konfiguracji na przedział z końcem wyłącznie
+fn get_range(range: &RawRange) -> Range<u32> {
+    get_ip(&range.start) .. get_ip(&range.end)+1
+}
+
 pub fn get_config(text: String) -> Config {
     let raw_config: RawConfig = serde_json::from_str(&text).unwrap();
-    let pool_range = get_ip(&raw_config.pool_start) .. get_ip(&raw_config.pool_end)+1;
+
+    let mut pool_ranges: Vec<Range<u32>> = raw_config.pool_ranges.iter().flat_map(|ranges| ranges.iter().map(get_range)).collect();
+    if let (Some(ref start), Some(ref end)) = (raw_config.pool_start, raw_config.pool_end) {
+        pool_ranges.push(get_ip(start) .. get_ip(end)+1);
+    }
+    if pool_ranges.is_empty() {
+        panic!("No address pool in config");
+    }
+    let exclusions: Vec<Range<u32>> = raw_config.exclusions.iter().flat_map(|ranges| ranges.iter().map(get_range)).collect();
     let dns: Vec<u32> = raw_config.dns.iter().map(|text| get_ip(text)).collect();
-    let mut statics: HashMap<u32, u64> = HashMap::new();
+    let mut statics: HashMap<u32, ClientId> = HashMap::new();
 
-    for (ip, mac) in raw_config.statics {
+    for (ip, client) in raw_config.statics {
         let ip = get_ip(&ip);
-        let mac = mac.parse::<HwAddr>().unwrap().octets();
-        let mac = Cursor::new(mac).read_uint::<NetworkEndian>(6).unwrap();
+        let client = client.parse::<ClientId>().expect("Invalid client identifier in statics");
+
+        statics.insert(ip, client);
+    }
+
+    let bootp_range = match (raw_config.bootp_pool_start, raw_config.bootp_pool_end) {
+        (Some(start), Some(end)) => Some(get_ip(&start) .. get_ip(&end)+1),
+        _ => None,
+    };
+
+    // Bez konfiguracji identyfikatorem serwera jest adres interfejsu, a gdy go nie ma - brama, jak dawniej
+    let gateway = get_ip(&raw_config.gateway);
+    let iface = &raw_config.iface;
+    let server_id = match raw_config.server_id {
+        Some(ref server_id) => get_ip(server_id),
+        None => interface_ipv4(iface).unwrap_or_else(|| {
+            println!("Couldn't get IPv4 address of {}, using gateway as server identifier", iface);
+            gateway
+        }),
+    };
+    let next_server = raw_config.next_server.as_ref().map(get_ip).unwrap_or(server_id);
+
+    // Sam plik bez wybranego magazynu oznacza dziennik JSON
+    let lease_store = match (raw_config.lease_store, &raw_config.lease_file) {
+        (Some(backend), _) => backend,
+        (None, &Some(_)) => Backend::Json,
+        (None, &None) => Backend::Memory,
+    };
+    if lease_store != Backend::Memory && raw_config.lease_file.is_none() {
+        panic!("lease_file is required for {:?} lease store", lease_store);
+    }
 
-        statics.insert(ip, mac);
+    // Domyślnie jak w RFC 2131 4.4.5: T1 w połowie dzierżawy, T2 po 7/8
+    let renewal_ratio = raw_config.renewal_ratio.unwrap_or(0.5);
+    let rebinding_ratio = raw_config.rebinding_ratio.unwrap_or(0.875);
+    let renewal_jitter = raw_config.renewal_jitter.unwrap_or(0.0);
+    if !(renewal_jitter >= 0.0 && renewal_ratio - renewal_jitter > 0.0
+        && renewal_ratio + renewal_jitter < rebinding_ratio && rebinding_ratio < 1.0) {
+        panic!("Renewal times must satisfy 0 < renewal_ratio ± renewal_jitter < rebinding_ratio < 1");
     }
 
     Config {
-        pool_range: pool_range,
+        pool_ranges: pool_ranges,
+        exclusions: exclusions,
         pool_mask: get_ip(&raw_config.pool_mask),
         dns: dns,
-        gateway: get_ip(&raw_config.gateway),
+        gateway: gateway,
         statics: statics,
         lease_time: raw_config.lease_time,
         expiration_time: raw_config.expiration_time,
         interface: raw_config.iface,
+        server_name: raw_config.server_name,
+        boot_file: raw_config.boot_file,
+        bootp_range: bootp_range,
+        server_id: server_id,
+        next_server: next_server,
+        reclaim_oldest_expired: raw_config.reclaim_oldest_expired.unwrap_or(false),
+        allocation_strategy: raw_config.allocation_strategy.unwrap_or(Strategy::Sequential),
+        offer_time: raw_config.offer_time.unwrap_or(60),
+        lease_file: raw_config.lease_file,
+        lease_store: lease_store,
+        history_size: raw_config.history_size.unwrap_or(65536),
+        renewal_ratio: renewal_ratio,
+        rebinding_ratio: rebinding_ratio,
+        renewal_jitter: renewal_jitter,
     }
 }
 
@@ -71,10 +183,13 @@ pub fn get_config(text: String) -> Config {
 fn serialize_config_test() {
     let mut statics = HashMap::new();
     statics.insert(String::from("192.168.0.3"), String::from("FF:FF:FF:FF:FF:FF"));
+    statics.insert(String::from("192.168.0.4"), String::from("id/01:FF:FF:FF:FF:FF:FF"));
 
     let config = RawConfig {
-        pool_start: String::from("192.168.0.2"),
-        pool_end: String::from("192.168.0.100"),
+        pool_start: Some(String::from("192.168.0.2")),
+        pool_end: Some(String::from("192.168.0.100")),
+        pool_ranges: Some(vec![RawRange { start: String::from("192.168.0.120"), end: String::from("192.168.0.150") }]),
+        exclusions: Some(vec![RawRange { start: String::from("192.168.0.50"), end: String::from("192.168.0.59") }]),
         pool_mask: String::from("255.255.255.0"),
         dns: vec![String::from("4.4.4.4"), String::from("8.8.8.8")],
         gateway: String::from("192.168.0.1"),
@@ -82,6 +197,21 @@ fn serialize_config_test() {
         lease_time: 300,
         expiration_time: 300,
         iface: String::from("eth0"),
+        server_name: None,
+        boot_file: Some(String::from("pxelinux.0")),
+        bootp_pool_start: Some(String::from("192.168.0.200")),
+        bootp_pool_end: Some(String::from("192.168.0.220")),
+        server_id: Some(String::from("192.168.0.2")),
+        next_server: None,
+        reclaim_oldest_expired: Some(true),
+        allocation_strategy: Some(Strategy::Hash),
+        offer_time: Some(30),
+        lease_file: Some(String::from("/var/lib/rust_dhcp/leases.sqlite")),
+        lease_store: Some(Backend::Sqlite),
+        history_size: Some(10000),
+        renewal_ratio: Some(0.5),
+        rebinding_ratio: Some(0.875),
+        renewal_jitter: Some(0.1),
     };
 
     println!("{}", serde_json::to_string(&config).unwrap());
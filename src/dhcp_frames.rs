@@ -1,10 +1,23 @@
 
 use std::io::Cursor;
 use std::io::Read;
-use std::io;
-use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use std::error::Error;
+use std::fmt;
+use std::u16;
+use byteorder::ReadBytesExt;
 use bytes::{Buf, BufMut};
-use std::collections::HashMap;
+use dhcp_options::*;
+use hw_address::HardwareAddress;
+use client_id::ClientId;
+
+const MAGIC_COOKIE: u32 = 0x63_82_53_63;
+// Nagłówek BOOTP z magic cookie
+const FIXED_LEN: usize = 240;
+const IP_UDP_HEADER_LEN: usize = 28;
+// Minimalna długość wiadomości BOOTP (RFC 951)
+const BOOTP_MIN_LEN: usize = 300;
+/// Najmniejsza dopuszczalna wartość opcji 57 (RFC 2132)
+pub const MIN_MAX_MESSAGE_SIZE: u16 = 576;
 
 // stała część pakietu DHCP
 pub struct DHCPHeader {
@@ -19,27 +32,244 @@ pub struct DHCPHeader {
     pub yiaddr: u32,
     pub siaddr: u32,
     pub giaddr: u32,
-    pub chaddr: u64,
+    pub chaddr: [u8; 16],
+    pub sname: [u8; 64],
+    pub file: [u8; 128],
+}
+
+impl DHCPHeader {
+    /// Adres sprzętowy klienta - typ łącza i pierwsze hlen bajtów chaddr
+    pub fn hardware_address(&self) -> HardwareAddress {
+        HardwareAddress::new(self.htype, &self.chaddr[..self.hlen as usize])
+    }
+
+    /// Nazwa serwera (sname) do pierwszego bajtu zerowego
+    #[cfg(test)]
+    pub fn sname(&self) -> String {
+        c_string(&self.sname)
+    }
+
+    /// Nazwa pliku startowego (file) do pierwszego bajtu zerowego
+    #[cfg(test)]
+    pub fn file(&self) -> String {
+        c_string(&self.file)
+    }
+
+    /// Ustawia sname, nadmiarowe bajty są obcinane, reszta pola wypełniona zerami
+    pub fn set_sname(&mut self, name: &str) {
+        self.sname = [0u8; 64];
+        copy_c_string(&mut self.sname, name);
+    }
+
+    /// Ustawia file, nadmiarowe bajty są obcinane, reszta pola wypełniona zerami
+    pub fn set_file(&mut self, name: &str) {
+        self.file = [0u8; 128];
+        copy_c_string(&mut self.file, name);
+    }
+}
+
+// Pola sname i file to napisy zakończone zerem
+#[cfg(test)]
+fn c_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+// Zostawiamy miejsce na zero kończące napis
+fn copy_c_string(field: &mut [u8], text: &str) {
+    let bytes = text.as_bytes();
+    let len = bytes.len().min(field.len() - 1);
+    field[..len].copy_from_slice(&bytes[..len]);
+}
+
+/// Błędy parsowania pakietu
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// Pakiet krótszy niż nagłówek BOOTP z magic cookie, podana długość
+    TruncatedHeader(usize),
+    /// Niepoprawne magic cookie
+    BadCookie(u32),
+    /// Opcja o danym kodzie urwana przed końcem pakietu
+    TruncatedOption(u8),
+    /// Długość adresu sprzętowego większa niż pole chaddr
+    BadHlen(u8),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::TruncatedHeader(len) => write!(f, "truncated header ({} bytes)", len),
+            ParseError::BadCookie(cookie) => write!(f, "bad magic cookie {:#010x}", cookie),
+            ParseError::TruncatedOption(code) => write!(f, "truncated option {}", code),
+            ParseError::BadHlen(hlen) => write!(f, "bad hardware address length {}", hlen),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Odczyt opcji aż do END. Zgodnie z RFC 3396 kolejne wystąpienia tego samego kodu są sklejane w jedną wartość,
+/// w kolejności wystąpienia.
+fn read_options<R: Read>(input: &mut R, options: &mut Vec<(u8, Vec<u8>)>) -> Result<(), ParseError> {
+    while let Some(code) = input.read_u8().ok() {
+        match code {
+            PAD => (),
+            END => break,
+            _ => {
+                let len = input.read_u8().map_err(|_| ParseError::TruncatedOption(code))?;
+                let mut v = vec![0u8; len as usize];
+                input.read_exact(&mut v[..]).map_err(|_| ParseError::TruncatedOption(code))?;
+
+                if let Some(&mut (_, ref mut value)) = options.iter_mut().find(|&&mut (c, _)| c == code) {
+                    value.extend_from_slice(&v);
+                    continue;
+                }
+                options.push((code, v));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Kodowanie opcji. Wartości dłuższe niż 255 bajtów dzielimy na kolejne wystąpienia tego samego kodu (RFC 3396).
+fn encode_option(code: u8, v: &[u8]) -> Vec<Vec<u8>> {
+    if v.is_empty() {
+        return vec![vec![code, 0]];
+    }
+
+    v.chunks(255).map(|chunk| {
+        let mut instance = Vec::with_capacity(chunk.len() + 2);
+        instance.put_u8(code);
+        instance.put_u8(chunk.len() as u8);
+        instance.put_slice(chunk);
+        instance
+    }).collect()
+}
+
+/// Rozmieszczenie opcji w polu options oraz, przy braku miejsca, w pustych polach file i sname (opcja 52).
+/// Zwraca zawartość pola options i listę (bit opcji 52, zawartość) przeniesionych pól.
+/// Gdy któraś opcja się nie mieści, zwraca None, chyba że `lossy` - wtedy ją pomija.
+fn layout_options(options: &[DhcpOption], header: &DHCPHeader, limit: usize, lossy: bool) -> Option<(Vec<u8>, Vec<(u8, Vec<u8>)>)> {
+    let mut instances: Vec<Vec<u8>> = Vec::new();
+    for option in options {
+        instances.extend(encode_option(option.code(), &option.value()));
+    }
+
+    // miejsce w polu options, bez znacznika END
+    let options_space = limit - FIXED_LEN - 1;
+    let total: usize = instances.iter().map(|instance| instance.len()).sum();
+    if total <= options_space {
+        return Some((instances.concat(), Vec::new()));
+    }
+
+    // (bit opcji 52, pojemność bez END, zawartość); w polu options rezerwujemy miejsce na opcję 52
+    let mut areas: Vec<(u8, usize, Vec<u8>)> = vec![(0, options_space - 3, Vec::new())];
+    if header.file.iter().all(|&b| b == 0) { areas.push((1, header.file.len() - 1, Vec::new())); }
+    if header.sname.iter().all(|&b| b == 0) { areas.push((2, header.sname.len() - 1, Vec::new())); }
+
+    // Wypełniamy obszary po kolei, żeby zachować kolejność sklejania: options, file, sname.
+    // Opcja, która nie mieści się w żadnym z pozostałych obszarów, nie przesuwa nas dalej - następne mogą się zmieścić.
+    let mut area = 0;
+    for instance in instances {
+        match (area..areas.len()).find(|&i| areas[i].2.len() + instance.len() <= areas[i].1) {
+            Some(i) => {
+                area = i;
+                areas[area].2.extend(instance);
+            },
+            None => {
+                if !lossy { return None; }
+                println!("Option {} does not fit in the message, dropping", instance[0]);
+            },
+        }
+    }
+
+    let mut options_field = Vec::new();
+    let overload = areas[1..].iter().filter(|a| !a.2.is_empty()).fold(0, |acc, a| acc | a.0);
+    if overload != 0 {
+        options_field.extend(encode_option(OPTION_OVERLOAD, &[overload]).concat());
+    }
+    options_field.extend(areas[0].2.iter());
+
+    let spilled = areas.into_iter().skip(1)
+        .filter(|a| !a.2.is_empty())
+        .map(|(bit, _, content)| (bit, content))
+        .collect();
+
+    Some((options_field, spilled))
 }
 
 // Część o stałej długości + opcje
 #[derive(Message)]
 pub struct DHCPPacket {
     pub header: DHCPHeader,
-    pub options: HashMap<u8, Vec<u8>>
+    pub options: Vec<DhcpOption>
 }
 
 
 impl DHCPPacket {
-    pub fn from_vec(v: Vec<u8>) -> io::Result<Self> {
+    /// Opcja o danym kodzie, o ile klient ją przysłał
+    pub fn option(&self, code: u8) -> Option<&DhcpOption> {
+        self.options.iter().find(|option| option.code() == code)
+    }
+
+    /// Typ wiadomości DHCP (opcja 53)
+    pub fn message_type(&self) -> Option<u8> {
+        match self.option(DHCP_MESSAGE_TYPE) {
+            Some(&DhcpOption::DhcpMessageType(t)) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// Adres IP, o który prosi klient (opcja 50)
+    pub fn requested_ip(&self) -> Option<u32> {
+        match self.option(REQUESTED_IP_ADDRESS) {
+            Some(&DhcpOption::RequestedIpAddress(ip)) => Some(ip),
+            _ => None,
+        }
+    }
+
+    /// Identyfikator serwera wybranego przez klienta (opcja 54)
+    pub fn server_identifier(&self) -> Option<u32> {
+        match self.option(DHCP_SERVER_IDENTIFIER) {
+            Some(&DhcpOption::ServerIdentifier(ip)) => Some(ip),
+            _ => None,
+        }
+    }
+
+    /// Lista opcji, o które prosi klient (opcja 55), w jego kolejności
+    pub fn parameter_request_list(&self) -> Option<&[u8]> {
+        match self.option(PARAMETER_REQUEST_LIST) {
+            Some(&DhcpOption::ParameterRequestList(ref codes)) => Some(codes),
+            _ => None,
+        }
+    }
+
+    /// Tożsamość klienta: identyfikator z opcji 61, a jeśli go nie ma, adres sprzętowy z nagłówka
+    pub fn client_id(&self) -> ClientId {
+        match self.option(CLIENT_IDENTIFIER) {
+            Some(&DhcpOption::ClientIdentifier(ref id)) => ClientId::Identifier(id.clone()),
+            _ => ClientId::Hardware(self.header.hardware_address()),
+        }
+    }
+
+    /// Maksymalny rozmiar wiadomości, jaki przyjmie klient (opcja 57)
+    pub fn max_message_size(&self) -> u16 {
+        match self.option(MAXIMUM_DHCP_MESSAGE_SIZE) {
+            Some(&DhcpOption::MaximumMessageSize(size)) => size,
+            _ => MIN_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    pub fn from_vec(v: Vec<u8>) -> Result<Self, ParseError> {
         let packet: DHCPPacket;
         let mut cursor = Cursor::new(v);
 
-        if cursor.remaining() < 236 { return Err(io::Error::from(io::ErrorKind::UnexpectedEof));}
+        if cursor.remaining() < FIXED_LEN { return Err(ParseError::TruncatedHeader(cursor.remaining())); }
 
         let op = cursor.get_u8();
         let htype = cursor.get_u8();
         let hlen = cursor.get_u8();
+        if hlen > 16 { return Err(ParseError::BadHlen(hlen)); }
         let hops = cursor.get_u8();
         let xid = cursor.get_u32_be();
         let secs = cursor.get_u16_be();
@@ -48,12 +278,16 @@ impl DHCPPacket {
         let yiaddr = cursor.get_u32_be();
         let siaddr = cursor.get_u32_be();
         let giaddr = cursor.get_u32_be();
-        let chaddr = cursor.read_uint::<NetworkEndian>(6).unwrap();
-
-        cursor.advance(202);
+        let mut chaddr = [0u8; 16];
+        cursor.copy_to_slice(&mut chaddr);
+        let mut sname = [0u8; 64];
+        cursor.copy_to_slice(&mut sname);
+        let mut file = [0u8; 128];
+        cursor.copy_to_slice(&mut file);
 
+        // Klienci BOOTP (RFC 951) mogą wysłać puste pole vendor, bez magic cookie - wtedy nie ma opcji
         let cookie = cursor.get_u32_be();
-        if cookie != 0x63_82_53_63 {return Err(io::Error::from(io::ErrorKind::UnexpectedEof));}
+        if cookie != MAGIC_COOKIE && cookie != 0 { return Err(ParseError::BadCookie(cookie)); }
 
         let header = DHCPHeader {
             op: op,
@@ -68,23 +302,24 @@ impl DHCPPacket {
             siaddr: siaddr,
             giaddr: giaddr,
             chaddr: chaddr,
+            sname: sname,
+            file: file,
         };
 
-        let mut options = HashMap::new();
-
-        while let Some(code) = cursor.read_u8().ok() {
-            match code {
-                0 => (),
-                255 => break,
-                _ => {
-                    let len = cursor.read_u8()?;
-                    let mut v = vec![0u8; len as usize];
-                    cursor.read_exact(&mut v[..])?;
-                    options.insert(code, v);
-                }
-            }
+        let mut raw_options = Vec::new();
+        if cookie == MAGIC_COOKIE {
+            read_options(&mut cursor, &mut raw_options)?;
         }
 
+        // Option overload - dalsze opcje w polach file i sname, w tej kolejności (RFC 3396)
+        let overload = raw_options.iter().find(|&&(code, _)| code == OPTION_OVERLOAD).and_then(|&(_, ref v)| v.first().cloned());
+        if let Some(overload) = overload {
+            if overload & 1 != 0 { read_options(&mut &header.file[..], &mut raw_options)?; }
+            if overload & 2 != 0 { read_options(&mut &header.sname[..], &mut raw_options)?; }
+        }
+
+        let options = raw_options.iter().map(|&(code, ref v)| DhcpOption::decode(code, v)).collect();
+
         packet = DHCPPacket {
             header: header,
             options: options,
@@ -93,9 +328,48 @@ impl DHCPPacket {
         Ok(packet)
     }
 
+    /// Serializacja bez ograniczenia rozmiaru wiadomości
+    #[cfg(test)]
     pub fn into_vec(self) -> Vec<u8> {
-        let mut output: Vec<u8> = Vec::with_capacity(236);
-        let header = self.header;
+        self.encode(u16::MAX)
+    }
+
+    /// Serializacja z uwzględnieniem maksymalnego rozmiaru wiadomości, jaki przyjmie klient (opcja 57, liczona razem z nagłówkami IP i UDP).
+    /// Jeśli opcje nie mieszczą się w polu options, a pola file i sname są puste, przenosimy do nich resztę opcji (opcja 52).
+    /// Jeśli to nie wystarczy, usuwamy opcje według `DhcpOption::drop_priority`. Krótsze wiadomości dopełniamy do 300 bajtów.
+    pub fn encode(self, max_size: u16) -> Vec<u8> {
+        let limit = max_size.max(MIN_MAX_MESSAGE_SIZE) as usize - IP_UDP_HEADER_LEN;
+        let mut header = self.header;
+
+        let mut options = self.options;
+
+        let (options_field, spilled) = loop {
+            if let Some(layout) = layout_options(&options, &header, limit, false) {
+                break layout;
+            }
+
+            // Nie mieści się - usuwamy opcję o najwyższym drop_priority, spośród równych tę najdalej na liście
+            let victim = options.iter().enumerate()
+                .filter(|&(_, option)| option.drop_priority() > 0)
+                .max_by_key(|&(i, option)| (option.drop_priority(), i))
+                .map(|(i, _)| i);
+
+            match victim {
+                Some(i) => {
+                    println!("Option {} does not fit in the message, dropping", options[i].code());
+                    options.remove(i);
+                },
+                None => break layout_options(&options, &header, limit, true).unwrap(),
+            }
+        };
+
+        for (bit, content) in spilled {
+            let field: &mut [u8] = if bit == 1 { &mut header.file } else { &mut header.sname };
+            field[..content.len()].copy_from_slice(&content);
+            field[content.len()] = END;
+        }
+
+        let mut output: Vec<u8> = Vec::with_capacity(FIXED_LEN + options_field.len() + 1);
 
         output.put_u8(header.op);
         output.put_u8(header.htype);
@@ -108,22 +382,150 @@ impl DHCPPacket {
         output.put_u32_be(header.yiaddr);
         output.put_u32_be(header.siaddr);
         output.put_u32_be(header.giaddr);
-        output.write_uint::<NetworkEndian>(header.chaddr, 6).unwrap();
+        output.put_slice(&header.chaddr);
+        output.put_slice(&header.sname);
+        output.put_slice(&header.file);
+        output.put_u32_be(MAGIC_COOKIE);
 
-        output.put_slice(&[0u8; 202]);
-        output.put_u32_be(0x63_82_53_63u32);
+        output.put_slice(&options_field);
+        output.put_u8(END);
 
-        let options: HashMap<u8, Vec<u8>> = self.options;
-
-        for (code, v) in options {
-            output.put_u8(code);
-            output.put_u8(v.len() as u8);
-            output.put(v);
+        // Część klientów BOOTP odrzuca wiadomości krótsze niż 300 bajtów
+        if output.len() < BOOTP_MIN_LEN {
+            output.resize(BOOTP_MIN_LEN, PAD);
         }
 
-        output.put_u8(255u8);
-
-
         output
     }
-}
\ No newline at end of file
+}
+
+/// sname, file i pełne chaddr przechodzą przez parsowanie i serializację bez zmian
+#[test]
+fn boot_fields_round_trip_test() {
+    let mut raw = vec![0u8; 240];
+    raw[0] = 1;
+    raw[1] = 1;
+    raw[2] = 6;
+    for i in 0..16 { raw[28 + i] = i as u8 + 1; }
+    raw[44..50].copy_from_slice(b"server");
+    raw[108..118].copy_from_slice(b"pxelinux.0");
+    raw[236..240].copy_from_slice(&[0x63, 0x82, 0x53, 0x63]);
+    raw.push(255);
+    raw.resize(300, 0);
+
+    let packet = DHCPPacket::from_vec(raw.clone()).unwrap();
+    assert_eq!(packet.header.sname(), "server");
+    assert_eq!(packet.header.file(), "pxelinux.0");
+    assert_eq!(packet.header.hardware_address(), HardwareAddress::new(1, &[1, 2, 3, 4, 5, 6]));
+
+    assert_eq!(packet.into_vec(), raw);
+}
+
+/// Długie opcje są dzielone przy zapisie i sklejane przy odczycie (RFC 3396)
+#[test]
+fn long_option_test() {
+    let dns: Vec<u32> = (0..100).collect();
+    let mut packet = DHCPPacket::from_vec(vec![0u8; 236].into_iter().chain(vec![0x63, 0x82, 0x53, 0x63, 255]).collect()).unwrap();
+    packet.options.push(DhcpOption::DomainNameServer(dns.clone()));
+
+    let raw = packet.into_vec();
+    assert_eq!(&raw[240..242], &[DOMAIN_NAME_SERVER, 255]);
+    assert_eq!(&raw[497..499], &[DOMAIN_NAME_SERVER, 145]);
+
+    let packet = DHCPPacket::from_vec(raw).unwrap();
+    assert_eq!(packet.options, vec![DhcpOption::DomainNameServer(dns)]);
+}
+
+/// Opcje, które nie mieszczą się w limicie klienta, trafiają do pól file i sname (opcja 52)
+#[test]
+fn option_overload_test() {
+    let mut packet = DHCPPacket::from_vec(vec![0u8; 236].into_iter().chain(vec![0x63, 0x82, 0x53, 0x63, 255]).collect()).unwrap();
+    packet.options.push(DhcpOption::DhcpMessageType(DHCP_ACK));
+    packet.options.push(DhcpOption::DomainNameServer((0..60).collect()));
+    packet.options.push(DhcpOption::DomainName(String::from("example.com")));
+    packet.options.push(DhcpOption::HostName(::std::iter::repeat("h").take(60).collect()));
+    let options = packet.options.clone();
+
+    let raw = packet.encode(MIN_MAX_MESSAGE_SIZE);
+    assert!(raw.len() <= MIN_MAX_MESSAGE_SIZE as usize - IP_UDP_HEADER_LEN);
+    assert_eq!(&raw[240..243], &[OPTION_OVERLOAD, 1, 1]);
+
+    let packet = DHCPPacket::from_vec(raw).unwrap();
+    let mut expected = vec![DhcpOption::OptionOverload(1)];
+    expected.extend(options);
+    assert_eq!(packet.options, expected);
+}
+
+/// Gdy nie starcza miejsca nawet w file i sname, najpierw usuwamy opcje o niskim priorytecie
+#[test]
+fn drop_low_priority_test() {
+    let mut packet = DHCPPacket::from_vec(vec![0u8; 236].into_iter().chain(vec![0x63, 0x82, 0x53, 0x63, 255]).collect()).unwrap();
+    packet.header.set_file("pxelinux.0");
+    packet.header.set_sname("server");
+    packet.options.push(DhcpOption::DhcpMessageType(DHCP_ACK));
+    packet.options.push(DhcpOption::NtpServers((0..40).collect()));
+    packet.options.push(DhcpOption::SubnetMask(0xFF_FF_FF_00));
+    packet.options.push(DhcpOption::DomainNameServer((0..40).collect()));
+
+    let raw = packet.encode(MIN_MAX_MESSAGE_SIZE);
+    let packet = DHCPPacket::from_vec(raw).unwrap();
+    assert_eq!(packet.options.iter().map(|o| o.code()).collect::<Vec<u8>>(), vec![DHCP_MESSAGE_TYPE, SUBNET_MASK, DOMAIN_NAME_SERVER]);
+}
+
+/// W trybie stratnym pomijamy tylko opcję, która się nie mieści - następne, np. identyfikator serwera, zostają
+#[test]
+fn lossy_layout_test() {
+    let mut packet = DHCPPacket::from_vec(vec![0u8; 236].into_iter().chain(vec![0x63, 0x82, 0x53, 0x63, 255]).collect()).unwrap();
+    packet.header.set_file("pxelinux.0");
+    packet.header.set_sname("server");
+    let options = vec![
+        DhcpOption::DomainNameServer((0..40).collect()),
+        DhcpOption::NtpServers((0..40).collect()),
+        DhcpOption::ServerIdentifier(0x0A_00_00_02),
+    ];
+
+    let limit = MIN_MAX_MESSAGE_SIZE as usize - IP_UDP_HEADER_LEN;
+    let (options_field, spilled) = layout_options(&options, &packet.header, limit, true).unwrap();
+    assert!(spilled.is_empty());
+    let mut expected = encode_option(DOMAIN_NAME_SERVER, &options[0].value()).concat();
+    expected.extend(encode_option(DHCP_SERVER_IDENTIFIER, &options[2].value()).concat());
+    assert_eq!(options_field, expected);
+}
+
+/// Krótkie odpowiedzi są dopełniane do minimalnej długości BOOTP
+#[test]
+fn bootp_padding_test() {
+    let mut packet = DHCPPacket::from_vec(vec![0u8; 236].into_iter().chain(vec![0x63, 0x82, 0x53, 0x63, 255]).collect()).unwrap();
+    packet.options.push(DhcpOption::DhcpMessageType(DHCP_NAK));
+    assert_eq!(packet.encode(MIN_MAX_MESSAGE_SIZE).len(), BOOTP_MIN_LEN);
+}
+
+/// Uszkodzone pakiety dają odpowiedni błąd zamiast paniki
+#[test]
+fn parse_error_test() {
+    let valid: Vec<u8> = vec![0u8; 236].into_iter().chain(vec![0x63, 0x82, 0x53, 0x63, 255]).collect();
+
+    assert_eq!(DHCPPacket::from_vec(valid[..100].to_vec()).err(), Some(ParseError::TruncatedHeader(100)));
+
+    let mut bad_cookie = valid.clone();
+    bad_cookie[239] = 0;
+    assert_eq!(DHCPPacket::from_vec(bad_cookie).err(), Some(ParseError::BadCookie(0x63_82_53_00)));
+
+    let mut bad_hlen = valid.clone();
+    bad_hlen[2] = 17;
+    assert_eq!(DHCPPacket::from_vec(bad_hlen).err(), Some(ParseError::BadHlen(17)));
+
+    let mut truncated_option = valid[..240].to_vec();
+    truncated_option.extend_from_slice(&[DOMAIN_NAME_SERVER, 8, 1, 2, 3]);
+    assert_eq!(DHCPPacket::from_vec(truncated_option).err(), Some(ParseError::TruncatedOption(DOMAIN_NAME_SERVER)));
+}
+
+/// Żądanie BOOTP z pustym polem vendor jest poprawnym pakietem bez opcji
+#[test]
+fn bootp_request_without_cookie_test() {
+    let mut raw = vec![0u8; 300];
+    raw[0] = 1;
+    let packet = DHCPPacket::from_vec(raw).unwrap();
+    assert!(packet.options.is_empty());
+    assert_eq!(packet.message_type(), None);
+}
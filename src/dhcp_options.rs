@@ -1,14 +1,39 @@
+use byteorder::{NetworkEndian, ReadBytesExt};
+use bytes::BufMut;
+
 //Stałe:
 
 
+pub const PAD: u8 = 0;
 pub const SUBNET_MASK: u8 = 1;
+pub const TIME_OFFSET: u8 = 2;
 pub const ROUTER: u8 = 3;
+pub const TIME_SERVER: u8 = 4;
 pub const DOMAIN_NAME_SERVER: u8 = 6;
+pub const LOG_SERVER: u8 = 7;
+pub const HOST_NAME: u8 = 12;
+pub const DOMAIN_NAME: u8 = 15;
+pub const INTERFACE_MTU: u8 = 26;
+pub const BROADCAST_ADDRESS: u8 = 28;
+pub const NTP_SERVERS: u8 = 42;
+pub const VENDOR_SPECIFIC: u8 = 43;
 
 pub const REQUESTED_IP_ADDRESS: u8 = 50;
 pub const IP_ADDRESS_LEASE_TIME: u8 = 51;
+pub const OPTION_OVERLOAD: u8 = 52;
 pub const DHCP_MESSAGE_TYPE: u8 = 53;
 pub const DHCP_SERVER_IDENTIFIER: u8= 54;
+pub const PARAMETER_REQUEST_LIST: u8 = 55;
+pub const MESSAGE: u8 = 56;
+pub const MAXIMUM_DHCP_MESSAGE_SIZE: u8 = 57;
+pub const RENEWAL_TIME: u8 = 58;
+pub const REBINDING_TIME: u8 = 59;
+pub const VENDOR_CLASS_IDENTIFIER: u8 = 60;
+pub const CLIENT_IDENTIFIER: u8 = 61;
+pub const TFTP_SERVER_NAME: u8 = 66;
+pub const BOOTFILE_NAME: u8 = 67;
+pub const DOMAIN_SEARCH: u8 = 119;
+pub const END: u8 = 255;
 
 
 pub const DHCP_OFFER: u8 = 2;
@@ -20,3 +45,217 @@ pub const DHCP_REQUEST: u8 = 3;
 pub const DHCP_DECLINE: u8 = 4;
 pub const DHCP_INFORM: u8 = 8;
 pub const DHCP_RELEASE: u8 = 7;
+
+
+/// Opcja DHCP z odkodowaną wartością. Adresy IP jako liczby, tak jak w reszcie serwera.
+/// Opcje, których nie znamy lub których wartość jest niepoprawna, trafiają do `Unknown` i są odsyłane bez zmian.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DhcpOption {
+    SubnetMask(u32),
+    TimeOffset(i32),
+    Router(Vec<u32>),
+    TimeServer(Vec<u32>),
+    DomainNameServer(Vec<u32>),
+    LogServer(Vec<u32>),
+    HostName(String),
+    DomainName(String),
+    InterfaceMtu(u16),
+    BroadcastAddress(u32),
+    NtpServers(Vec<u32>),
+    VendorSpecific(Vec<u8>),
+    RequestedIpAddress(u32),
+    IpAddressLeaseTime(u32),
+    OptionOverload(u8),
+    DhcpMessageType(u8),
+    ServerIdentifier(u32),
+    ParameterRequestList(Vec<u8>),
+    Message(String),
+    MaximumMessageSize(u16),
+    RenewalTime(u32),
+    RebindingTime(u32),
+    VendorClassIdentifier(Vec<u8>),
+    ClientIdentifier(Vec<u8>),
+    TftpServerName(String),
+    BootfileName(String),
+    DomainSearch(Vec<u8>),
+    Unknown(u8, Vec<u8>),
+}
+
+// Pojedynczy adres IP, dokładnie 4 bajty
+fn decode_ip(v: &[u8]) -> Option<u32> {
+    if v.len() != 4 { return None; }
+    (&v[..]).read_u32::<NetworkEndian>().ok()
+}
+
+// Lista adresów IP, co najmniej jeden
+fn decode_ip_list(v: &[u8]) -> Option<Vec<u32>> {
+    if v.is_empty() || v.len() % 4 != 0 { return None; }
+    Some(v.chunks(4).map(|ip| (&ip[..]).read_u32::<NetworkEndian>().unwrap()).collect())
+}
+
+fn decode_u16(v: &[u8]) -> Option<u16> {
+    if v.len() != 2 { return None; }
+    (&v[..]).read_u16::<NetworkEndian>().ok()
+}
+
+fn decode_u8(v: &[u8]) -> Option<u8> {
+    if v.len() != 1 { return None; }
+    Some(v[0])
+}
+
+fn decode_string(v: &[u8]) -> Option<String> {
+    if v.is_empty() { return None; }
+    String::from_utf8(v.to_vec()).ok()
+}
+
+fn decode_bytes(v: &[u8]) -> Option<Vec<u8>> {
+    if v.is_empty() { return None; }
+    Some(v.to_vec())
+}
+
+fn encode_ip_list(ips: &[u32]) -> Vec<u8> {
+    let mut v = Vec::with_capacity(ips.len() * 4);
+    for ip in ips {
+        v.put_u32_be(*ip);
+    }
+    v
+}
+
+impl DhcpOption {
+    /// Dekodowanie wartości opcji o danym kodzie
+    pub fn decode(code: u8, v: &[u8]) -> DhcpOption {
+        use self::DhcpOption::*;
+
+        let option = match code {
+            SUBNET_MASK => decode_ip(v).map(SubnetMask),
+            TIME_OFFSET => decode_ip(v).map(|t| TimeOffset(t as i32)),
+            ROUTER => decode_ip_list(v).map(Router),
+            TIME_SERVER => decode_ip_list(v).map(TimeServer),
+            DOMAIN_NAME_SERVER => decode_ip_list(v).map(DomainNameServer),
+            LOG_SERVER => decode_ip_list(v).map(LogServer),
+            HOST_NAME => decode_string(v).map(HostName),
+            DOMAIN_NAME => decode_string(v).map(DomainName),
+            INTERFACE_MTU => decode_u16(v).map(InterfaceMtu),
+            BROADCAST_ADDRESS => decode_ip(v).map(BroadcastAddress),
+            NTP_SERVERS => decode_ip_list(v).map(NtpServers),
+            VENDOR_SPECIFIC => decode_bytes(v).map(VendorSpecific),
+            REQUESTED_IP_ADDRESS => decode_ip(v).map(RequestedIpAddress),
+            IP_ADDRESS_LEASE_TIME => decode_ip(v).map(IpAddressLeaseTime),
+            OPTION_OVERLOAD => decode_u8(v).map(OptionOverload),
+            DHCP_MESSAGE_TYPE => decode_u8(v).map(DhcpMessageType),
+            DHCP_SERVER_IDENTIFIER => decode_ip(v).map(ServerIdentifier),
+            PARAMETER_REQUEST_LIST => decode_bytes(v).map(ParameterRequestList),
+            MESSAGE => decode_string(v).map(Message),
+            MAXIMUM_DHCP_MESSAGE_SIZE => decode_u16(v).map(MaximumMessageSize),
+            RENEWAL_TIME => decode_ip(v).map(RenewalTime),
+            REBINDING_TIME => decode_ip(v).map(RebindingTime),
+            VENDOR_CLASS_IDENTIFIER => decode_bytes(v).map(VendorClassIdentifier),
+            CLIENT_IDENTIFIER => decode_bytes(v).map(ClientIdentifier),
+            TFTP_SERVER_NAME => decode_string(v).map(TftpServerName),
+            BOOTFILE_NAME => decode_string(v).map(BootfileName),
+            DOMAIN_SEARCH => decode_bytes(v).map(DomainSearch),
+            _ => None,
+        };
+
+        option.unwrap_or_else(|| Unknown(code, v.to_vec()))
+    }
+
+    /// Kod opcji
+    pub fn code(&self) -> u8 {
+        use self::DhcpOption::*;
+
+        match *self {
+            SubnetMask(_) => SUBNET_MASK,
+            TimeOffset(_) => TIME_OFFSET,
+            Router(_) => ROUTER,
+            TimeServer(_) => TIME_SERVER,
+            DomainNameServer(_) => DOMAIN_NAME_SERVER,
+            LogServer(_) => LOG_SERVER,
+            HostName(_) => HOST_NAME,
+            DomainName(_) => DOMAIN_NAME,
+            InterfaceMtu(_) => INTERFACE_MTU,
+            BroadcastAddress(_) => BROADCAST_ADDRESS,
+            NtpServers(_) => NTP_SERVERS,
+            VendorSpecific(_) => VENDOR_SPECIFIC,
+            RequestedIpAddress(_) => REQUESTED_IP_ADDRESS,
+            IpAddressLeaseTime(_) => IP_ADDRESS_LEASE_TIME,
+            OptionOverload(_) => OPTION_OVERLOAD,
+            DhcpMessageType(_) => DHCP_MESSAGE_TYPE,
+            ServerIdentifier(_) => DHCP_SERVER_IDENTIFIER,
+            ParameterRequestList(_) => PARAMETER_REQUEST_LIST,
+            Message(_) => MESSAGE,
+            MaximumMessageSize(_) => MAXIMUM_DHCP_MESSAGE_SIZE,
+            RenewalTime(_) => RENEWAL_TIME,
+            RebindingTime(_) => REBINDING_TIME,
+            VendorClassIdentifier(_) => VENDOR_CLASS_IDENTIFIER,
+            ClientIdentifier(_) => CLIENT_IDENTIFIER,
+            TftpServerName(_) => TFTP_SERVER_NAME,
+            BootfileName(_) => BOOTFILE_NAME,
+            DomainSearch(_) => DOMAIN_SEARCH,
+            Unknown(code, _) => code,
+        }
+    }
+
+    /// Kolejność usuwania opcji, gdy odpowiedź nie mieści się w limicie klienta. Najpierw usuwamy opcje z najwyższą wartością,
+    /// opcji z wartością 0 nie usuwamy nigdy.
+    pub fn drop_priority(&self) -> u8 {
+        use self::DhcpOption::*;
+
+        match *self {
+            DhcpMessageType(_) | ServerIdentifier(_) | IpAddressLeaseTime(_) | RenewalTime(_) | RebindingTime(_)
+            | OptionOverload(_) => 0,
+            SubnetMask(_) | Router(_) | Message(_) => 1,
+            DomainNameServer(_) | DomainName(_) | BroadcastAddress(_) | InterfaceMtu(_) => 2,
+            TftpServerName(_) | BootfileName(_) | HostName(_) | DomainSearch(_) => 3,
+            TimeOffset(_) | TimeServer(_) | LogServer(_) | NtpServers(_) => 4,
+            _ => 5,
+        }
+    }
+
+    /// Zakodowana wartość opcji, bez kodu i długości
+    pub fn value(&self) -> Vec<u8> {
+        use self::DhcpOption::*;
+
+        let mut v = Vec::new();
+        match *self {
+            SubnetMask(ip) | BroadcastAddress(ip) | RequestedIpAddress(ip) | ServerIdentifier(ip) => v.put_u32_be(ip),
+            IpAddressLeaseTime(t) | RenewalTime(t) | RebindingTime(t) => v.put_u32_be(t),
+            TimeOffset(t) => v.put_i32_be(t),
+            Router(ref ips) | TimeServer(ref ips) | DomainNameServer(ref ips) | LogServer(ref ips) | NtpServers(ref ips) => {
+                v = encode_ip_list(ips);
+            },
+            HostName(ref s) | DomainName(ref s) | Message(ref s) | TftpServerName(ref s) | BootfileName(ref s) => {
+                v.put_slice(s.as_bytes());
+            },
+            InterfaceMtu(n) | MaximumMessageSize(n) => v.put_u16_be(n),
+            OptionOverload(n) | DhcpMessageType(n) => v.put_u8(n),
+            VendorSpecific(ref bytes) | ParameterRequestList(ref bytes) | VendorClassIdentifier(ref bytes)
+            | ClientIdentifier(ref bytes) | DomainSearch(ref bytes) | Unknown(_, ref bytes) => {
+                v.put_slice(bytes);
+            },
+        }
+        v
+    }
+}
+
+
+/// Każda znana opcja po zakodowaniu i odkodowaniu jest taka sama
+#[test]
+fn option_round_trip_test() {
+    let options = vec![
+        DhcpOption::SubnetMask(0xFF_FF_FF_00),
+        DhcpOption::TimeOffset(-3600),
+        DhcpOption::DomainNameServer(vec![0x04_04_04_04, 0x08_08_08_08]),
+        DhcpOption::HostName(String::from("printer")),
+        DhcpOption::MaximumMessageSize(1500),
+        DhcpOption::DhcpMessageType(DHCP_ACK),
+        DhcpOption::ParameterRequestList(vec![1, 3, 6]),
+        DhcpOption::Unknown(224, vec![1, 2, 3]),
+    ];
+
+    for option in options {
+        assert_eq!(DhcpOption::decode(option.code(), &option.value()), option);
+    }
+
+    assert_eq!(DhcpOption::decode(SUBNET_MASK, &[255, 255]), DhcpOption::Unknown(SUBNET_MASK, vec![255, 255]));
+}
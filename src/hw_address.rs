@@ -0,0 +1,106 @@
+use std::fmt;
+use std::str::FromStr;
+
+pub const HTYPE_ETHERNET: u8 = 1;
+#[cfg(test)]
+pub const HTYPE_INFINIBAND: u8 = 32;
+
+/// Adres sprzętowy klienta: typ łącza (htype) i do 16 bajtów z pola chaddr, z których znaczące jest pierwsze hlen.
+/// Dwa adresy są równe tylko przy tym samym typie łącza.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HardwareAddress {
+    htype: u8,
+    len: u8,
+    bytes: [u8; 16],
+}
+
+impl HardwareAddress {
+    /// Adres z typu łącza i bajtów adresu. Bajty ponad 16 są obcinane.
+    pub fn new(htype: u8, address: &[u8]) -> Self {
+        let len = address.len().min(16);
+        let mut bytes = [0u8; 16];
+        bytes[..len].copy_from_slice(&address[..len]);
+
+        HardwareAddress {
+            htype: htype,
+            len: len as u8,
+            bytes: bytes,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn htype(&self) -> u8 {
+        self.htype
+    }
+
+    /// Znaczące bajty adresu (hlen)
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+/// Ethernet jako aa:bb:cc:dd:ee:ff, pozostałe typy łącza z prefiksem typu, np. 32/aa:bb:...
+impl fmt::Display for HardwareAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.htype != HTYPE_ETHERNET {
+            write!(f, "{}/", self.htype)?;
+        }
+        let octets: Vec<String> = self.bytes().iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{}", octets.join(":"))
+    }
+}
+
+impl fmt::Debug for HardwareAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseHardwareAddressError;
+
+/// Format jak w Display. Bajty oddzielone `:` lub `-`, bez prefiksu typu przyjmujemy Ethernet. Pusty adres to
+/// adres klienta z hlen 0 - tak go zapisujemy w magazynie dzierżaw, więc musi się dać odczytać.
+impl FromStr for HardwareAddress {
+    type Err = ParseHardwareAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (htype, address) = match s.find('/') {
+            Some(i) => (s[..i].parse::<u8>().map_err(|_| ParseHardwareAddressError)?, &s[i + 1..]),
+            None => (HTYPE_ETHERNET, s),
+        };
+
+        let bytes = if address.is_empty() {
+            Vec::new()
+        } else {
+            address.split(|c| c == ':' || c == '-')
+                .map(|octet| u8::from_str_radix(octet, 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| ParseHardwareAddressError)?
+        };
+
+        if bytes.len() > 16 { return Err(ParseHardwareAddressError); }
+
+        Ok(HardwareAddress::new(htype, &bytes))
+    }
+}
+
+
+/// Adresy Ethernet i InfiniBand z konfiguracji
+#[test]
+fn parse_hardware_address_test() {
+    let mac: HardwareAddress = "08:00:27:73:42:4E".parse().unwrap();
+    assert_eq!(mac, HardwareAddress::new(HTYPE_ETHERNET, &[0x08, 0x00, 0x27, 0x73, 0x42, 0x4E]));
+    assert_eq!(mac.to_string(), "08:00:27:73:42:4E");
+
+    let ib: HardwareAddress = "32/80:00:00:48:fe:80:00:00:00:00:00:00:00:02:c9:03".parse().unwrap();
+    assert_eq!(ib.htype(), HTYPE_INFINIBAND);
+    assert_eq!(ib.bytes().len(), 16);
+    assert!(ib != HardwareAddress::new(HTYPE_ETHERNET, ib.bytes()));
+
+    assert!("08:00:27:73:42:4G".parse::<HardwareAddress>().is_err());
+
+    for empty in &[HardwareAddress::new(HTYPE_ETHERNET, &[]), HardwareAddress::new(HTYPE_INFINIBAND, &[])] {
+        assert_eq!(empty.to_string().parse::<HardwareAddress>(), Ok(*empty));
+    }
+}
@@ -0,0 +1,122 @@
+use libc;
+use std::ffi::CStr;
+use std::io;
+use std::mem;
+use std::net::{UdpSocket, SocketAddr, IpAddr, Ipv4Addr};
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+
+/// Adres IPv4 interfejsu o podanej nazwie, o ile jakiś ma. Przy kilku adresach bierzemy pierwszy zwrócony przez getifaddrs.
+pub fn interface_ipv4(iface: &str) -> Option<u32> {
+    let mut addrs: *mut libc::ifaddrs = ptr::null_mut();
+
+    unsafe {
+        if libc::getifaddrs(&mut addrs) != 0 {
+            return None;
+        }
+
+        let mut result = None;
+        let mut current = addrs;
+        while !current.is_null() {
+            let ifa = &*current;
+            let is_ipv4 = !ifa.ifa_addr.is_null() && (*ifa.ifa_addr).sa_family as i32 == libc::AF_INET;
+
+            if is_ipv4 && CStr::from_ptr(ifa.ifa_name).to_bytes() == iface.as_bytes() {
+                let addr = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                result = Some(u32::from_be(addr.sin_addr.s_addr));
+                break;
+            }
+
+            current = ifa.ifa_next;
+        }
+
+        libc::freeifaddrs(addrs);
+        result
+    }
+}
+
+/// Włączenie IP_PKTINFO - jądro poda przy każdym datagramie adres, na który został wysłany
+pub fn enable_pktinfo(socket: &UdpSocket) -> io::Result<()> {
+    let on: libc::c_int = 1;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_PKTINFO,
+            &on as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t
+        )
+    };
+    if result == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+/// Odpowiednik recv_from, który zwraca też adres docelowy datagramu z nagłówka IP.
+/// None, gdy jądro go nie podało, np. bez IP_PKTINFO na sockecie.
+pub fn recv_with_destination(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<u32>)> {
+    let mut source: libc::sockaddr_in = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: buf.len() };
+    // u64, żeby nagłówki danych pomocniczych były wyrównane
+    let mut control = [0u64; 16];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut source as *mut libc::sockaddr_in as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let ip = Ipv4Addr::from(u32::from_be(source.sin_addr.s_addr));
+    let addr = SocketAddr::new(IpAddr::from(ip), u16::from_be(source.sin_port));
+    Ok((len as usize, addr, unsafe { pktinfo_destination(&msg) }))
+}
+
+/// Adres docelowy z danych pomocniczych IP_PKTINFO. Nagłówki przeglądamy tak jak CMSG_FIRSTHDR i CMSG_NXTHDR,
+/// których biblioteka libc nie udostępnia.
+unsafe fn pktinfo_destination(msg: &libc::msghdr) -> Option<u32> {
+    let align = |len: usize| (len + mem::size_of::<usize>() - 1) & !(mem::size_of::<usize>() - 1);
+    let header_len = mem::size_of::<libc::cmsghdr>();
+    let start = msg.msg_control as *const u8;
+    let end = start.offset(msg.msg_controllen as isize);
+
+    let mut current = start;
+    while current.offset(header_len as isize) <= end {
+        let header = &*(current as *const libc::cmsghdr);
+        if (header.cmsg_len as usize) < header_len { break; }
+
+        if header.cmsg_level == libc::IPPROTO_IP && header.cmsg_type == libc::IP_PKTINFO {
+            let info = &*(current.offset(align(header_len) as isize) as *const libc::in_pktinfo);
+            return Some(u32::from_be(info.ipi_addr.s_addr));
+        }
+        current = current.offset(align(header.cmsg_len as usize) as isize);
+    }
+    None
+}
+
+
+/// Interfejs pętli zwrotnej ma zawsze 127.0.0.1
+#[test]
+fn loopback_address_test() {
+    assert_eq!(interface_ipv4("lo"), Some(0x7F_00_00_01));
+    assert_eq!(interface_ipv4("no-such-iface"), None);
+}
+
+/// Datagram wysłany na 127.0.0.1 przychodzi z tym adresem docelowym
+#[test]
+fn pktinfo_destination_test() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    enable_pktinfo(&receiver).unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.send_to(b"dhcp", receiver.local_addr().unwrap()).unwrap();
+
+    let mut buf = [0u8; 16];
+    let (len, addr, destination) = recv_with_destination(&receiver, &mut buf).unwrap();
+    assert_eq!(&buf[..len], b"dhcp");
+    assert_eq!(addr, sender.local_addr().unwrap());
+    assert_eq!(destination, Some(0x7F_00_00_01));
+}
@@ -3,6 +3,23 @@ use std::net::{UdpSocket, SocketAddr, IpAddr, Ipv4Addr};
 use dhcp_frames::DHCPPacket;
 
 
+/// Odpowiedź serwera wraz z maksymalnym rozmiarem wiadomości, jaki przyjmie klient,
+/// i adresem klienta, jeśli nie wysyłamy na broadcast
+#[derive(Message)]
+pub struct OutgoingPacket {
+    pub packet: DHCPPacket,
+    pub max_size: u16,
+    pub unicast: Option<u32>,
+}
+
+/// Pakiet od klienta wraz z adresem, na który go wysłał - odróżnia unicast do serwera od broadcastu.
+/// None, gdy adresu nie znamy.
+#[derive(Message)]
+pub struct IncomingPacket {
+    pub packet: DHCPPacket,
+    pub destination: Option<u32>,
+}
+
 /// Socket i adres do wysłania
 pub struct OutputActor {
     socket: UdpSocket,
@@ -13,13 +30,17 @@ impl Actor for OutputActor {
     type Context = Context<Self>;
 }
 
-impl Handler<DHCPPacket> for OutputActor {
+impl Handler<OutgoingPacket> for OutputActor {
     type Result = ();
 
-    /// Wysyłamy otrzymane wiadomości na socket, na adres do broadcastu.
-    fn handle(&mut self, msg: DHCPPacket, _ctx: &mut Context<Self>)  {
-        println!("Sending frame to {}", self.bcast_addr);
-        let _ = self.socket.send_to(msg.into_vec().as_slice(), self.bcast_addr);
+    /// Wysyłamy otrzymane wiadomości na socket, na adres do broadcastu albo bezpośrednio do klienta.
+    fn handle(&mut self, msg: OutgoingPacket, _ctx: &mut Context<Self>)  {
+        let addr = match msg.unicast {
+            Some(ip) => SocketAddr::new(IpAddr::from(Ipv4Addr::from(ip)), 68),
+            None => self.bcast_addr,
+        };
+        println!("Sending frame to {}", addr);
+        let _ = self.socket.send_to(msg.packet.encode(msg.max_size).as_slice(), addr);
     }
 }
 
@@ -0,0 +1,120 @@
+use serde_json;
+use lease_store::Status;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+
+/// Wpis dziennika dzierżaw, jeden wiersz JSON. Odpowiednio: nowy stan adresu z czasami jak w `Lease` (sekundy od epoki Uniksa),
+/// zwolnienie adresu, ostatni adres klienta.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum JournalEntry {
+    Lease { ip: Ipv4Addr, client: String, status: Status, issued: Option<u64>, expires: Option<u64>, expired: Option<u64>, seen: Option<u64> },
+    Free { ip: Ipv4Addr },
+    History { ip: Ipv4Addr, client: String },
+}
+
+/// Dziennik dzierżaw dopisywany przy każdej zmianie stanu. Co jakiś czas zastępujemy go samym bieżącym stanem,
+/// żeby nie rósł bez końca.
+pub struct LeaseJournal {
+    path: PathBuf,
+    file: File,
+    appended: usize,
+}
+
+impl LeaseJournal {
+    /// Otwarcie dziennika razem z zapisanymi wpisami. Nieczytelne wiersze (np. urwany ostatni wiersz po awarii) pomijamy.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<(Self, Vec<JournalEntry>)> {
+        let path = path.as_ref().to_path_buf();
+        let mut entries = Vec::new();
+
+        if path.exists() {
+            let reader = BufReader::new(File::open(&path)?);
+            for line in reader.lines() {
+                let line = line?;
+                if line.trim().is_empty() { continue; }
+                match serde_json::from_str(&line) {
+                    Ok(entry) => entries.push(entry),
+                    Err(e) => println!("Skipping bad lease journal line {:?}: {}", line, e),
+                }
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok((LeaseJournal { path: path, file: file, appended: 0 }, entries))
+    }
+
+    /// Dopisanie wpisu. Wracamy dopiero, gdy wpis jest na dysku.
+    pub fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        let line = serde_json::to_string(entry).unwrap();
+        writeln!(self.file, "{}", line)?;
+        self.file.sync_data()?;
+        self.appended += 1;
+        Ok(())
+    }
+
+    /// Liczba wpisów dopisanych od otwarcia lub ostatniego kompaktowania
+    pub fn appended(&self) -> usize {
+        self.appended
+    }
+
+    /// Zastąpienie dziennika podanymi wpisami. Piszemy do pliku tymczasowego i podmieniamy go, więc awaria
+    /// w trakcie zostawia stary dziennik w całości.
+    pub fn compact(&mut self, entries: &[JournalEntry]) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            for entry in entries {
+                writeln!(tmp, "{}", serde_json::to_string(entry).unwrap())?;
+            }
+            tmp.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.appended = 0;
+        Ok(())
+    }
+}
+
+
+/// Wpisy dopisane i po kompaktowaniu wracają przy ponownym otwarciu, urwany wiersz jest pomijany
+#[test]
+fn journal_replay_test() {
+    use std::process;
+
+    let path = ::std::env::temp_dir().join(format!("rust_dhcp_journal_test_{}", process::id()));
+    let _ = fs::remove_file(&path);
+
+    let lease = JournalEntry::Lease {
+        ip: Ipv4Addr::new(10, 0, 0, 10),
+        client: String::from("08:00:27:73:42:4E"),
+        status: Status::Leasing,
+        issued: Some(1_599_999_700),
+        expires: Some(1_600_000_000),
+        expired: None,
+        seen: None,
+    };
+    let free = JournalEntry::Free { ip: Ipv4Addr::new(10, 0, 0, 11) };
+
+    {
+        let (mut journal, entries) = LeaseJournal::open(&path).unwrap();
+        assert!(entries.is_empty());
+        journal.append(&lease).unwrap();
+        journal.append(&free).unwrap();
+        assert_eq!(journal.appended(), 2);
+    }
+    OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"op\":\"lea").unwrap();
+
+    {
+        let (mut journal, entries) = LeaseJournal::open(&path).unwrap();
+        assert_eq!(entries, vec![lease, free]);
+        journal.compact(&[JournalEntry::Free { ip: Ipv4Addr::new(10, 0, 0, 12) }]).unwrap();
+        assert_eq!(journal.appended(), 0);
+    }
+
+    let (_, entries) = LeaseJournal::open(&path).unwrap();
+    assert_eq!(entries, vec![JournalEntry::Free { ip: Ipv4Addr::new(10, 0, 0, 12) }]);
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,437 @@
+use client_id::ClientId;
+use lease_journal::{LeaseJournal, JournalEntry};
+use sqlite_store::SqliteStore;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::Ipv4Addr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Stan danego adresu IP serwera. Odpowiednio, zaoferowany w DHCPOFFER i czekający na DHCPREQUEST, dzierżawiony,
+/// zarezerwowany dla przydzielania statycznego, wygasły, odrzucony przez DHCPDECLINE, przydzielony klientowi BOOTP na czas nieograniczony
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Offered,
+    Leasing,
+    Reserved,
+    Expiring,
+    Declined,
+    Bootp,
+}
+
+/// Wpis dla adresu: klient, stan, moment przydzielenia lub ostatniego przedłużenia dzierżawy, czas bezwzględny, w którym upływa
+/// obecny stan, moment wygaśnięcia dzierżawy, jeśli już wygasła, i ostatni pakiet klienta w sprawie tego adresu
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lease {
+    pub ip: u32,
+    pub client: ClientId,
+    pub status: Status,
+    pub issued_at: Option<SystemTime>,
+    pub expires_at: Option<SystemTime>,
+    pub expired_at: Option<SystemTime>,
+    pub last_seen: Option<SystemTime>,
+}
+
+/// Gdzie serwer trzyma dzierżawy: tylko w pamięci, w dzienniku JSON albo w bazie SQLite
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Memory,
+    Json,
+    Sqlite,
+}
+
+/// Magazyn dzierżaw. Wpisy zwracamy przez wartość, bo trwałe magazyny i tak nie trzymają ich w pamięci.
+/// Zmiany zwracają błąd zapisu - serwer nie może potwierdzić dzierżawy, której nie zapamiętał. Błędy odczytu
+/// magazyn tylko zgłasza w logu i odpowiada tak, jakby wpisu nie było.
+pub trait LeaseStore {
+    /// Wpis dla adresu
+    fn get(&self, ip: u32) -> Option<Lease>;
+
+    /// Wszystkie wpisy klienta
+    fn by_client(&self, client: &ClientId) -> Vec<Lease>;
+
+    /// Nowy wpis albo zastąpienie wpisu dla tego samego adresu. Zwraca poprzedni.
+    fn insert(&mut self, lease: Lease) -> io::Result<Option<Lease>>;
+
+    /// Zmiana istniejącego wpisu. False, jeśli adresu nie ma w magazynie.
+    fn update(&mut self, lease: Lease) -> io::Result<bool>;
+
+    /// Usunięcie wpisu - po wygaśnięciu, zwolnieniu albo odrzuceniu oferty
+    fn expire(&mut self, ip: u32) -> io::Result<Option<Lease>>;
+
+    /// Wszystkie wpisy
+    fn leases(&self) -> Vec<Lease>;
+
+    /// Ostatni dynamiczny adres klienta, pamiętany także po wygaśnięciu dzierżawy
+    fn last_address(&self, client: &ClientId) -> Option<u32>;
+
+    /// Zapamiętanie ostatniego adresu klienta. Ponad limit historii zapominamy klientów obsłużonych najdawniej.
+    fn remember(&mut self, client: &ClientId, ip: u32) -> io::Result<()>;
+
+    fn contains(&self, ip: u32) -> bool {
+        self.get(ip).is_some()
+    }
+}
+
+/// Magazyn wybrany w konfiguracji, z limitem historii klientów. Magazyny trwałe potrzebują ścieżki pliku.
+pub fn open_store(backend: Backend, path: Option<&String>, history_size: usize) -> Box<dyn LeaseStore> {
+    match (backend, path) {
+        (Backend::Memory, _) => Box::new(MemoryStore::new(history_size)),
+        (Backend::Json, Some(path)) => Box::new(JsonFileStore::open(path, history_size).expect("Couldn't open lease journal")),
+        (Backend::Sqlite, Some(path)) => Box::new(SqliteStore::open(path, history_size).expect("Couldn't open lease database")),
+        (_, None) => panic!("lease_file is required for {:?} lease store", backend),
+    }
+}
+
+/// Czas jako sekundy od epoki Uniksa, tak zapisujemy go w plikach
+pub fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+pub fn from_unix_secs(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Dzierżawy i historia klientów w mapach, z indeksem adresów klienta - szukamy po nim przy każdym DHCPREQUEST.
+/// Wpisy historii mają numery kolejne, według nich zapominamy najdawniej obsłużonych klientów.
+pub struct MemoryStore {
+    leases: HashMap<u32, Lease>,
+    clients: HashMap<ClientId, Vec<u32>>,
+    history: HashMap<ClientId, (u64, u32)>,
+    history_order: BTreeMap<u64, ClientId>,
+    history_seq: u64,
+    history_size: usize,
+}
+
+impl MemoryStore {
+    pub fn new(history_size: usize) -> Self {
+        MemoryStore {
+            leases: HashMap::new(),
+            clients: HashMap::new(),
+            history: HashMap::new(),
+            history_order: BTreeMap::new(),
+            history_seq: 0,
+            history_size: history_size,
+        }
+    }
+
+    fn index(&mut self, lease: &Lease) {
+        self.clients.entry(lease.client.clone()).or_insert_with(Vec::new).push(lease.ip);
+    }
+
+    fn unindex(&mut self, lease: &Lease) {
+        let empty = match self.clients.get_mut(&lease.client) {
+            Some(ips) => {
+                ips.retain(|&ip| ip != lease.ip);
+                ips.is_empty()
+            },
+            None => false,
+        };
+        if empty {
+            self.clients.remove(&lease.client);
+        }
+    }
+}
+
+impl LeaseStore for MemoryStore {
+    fn get(&self, ip: u32) -> Option<Lease> {
+        self.leases.get(&ip).cloned()
+    }
+
+    fn by_client(&self, client: &ClientId) -> Vec<Lease> {
+        match self.clients.get(client) {
+            Some(ips) => ips.iter().filter_map(|ip| self.leases.get(ip)).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, lease: Lease) -> io::Result<Option<Lease>> {
+        let prev_lease = self.leases.remove(&lease.ip);
+        if let Some(ref prev) = prev_lease {
+            self.unindex(prev);
+        }
+        self.index(&lease);
+        self.leases.insert(lease.ip, lease);
+        Ok(prev_lease)
+    }
+
+    fn update(&mut self, lease: Lease) -> io::Result<bool> {
+        if !self.leases.contains_key(&lease.ip) { return Ok(false); }
+        self.insert(lease)?;
+        Ok(true)
+    }
+
+    fn expire(&mut self, ip: u32) -> io::Result<Option<Lease>> {
+        let lease = self.leases.remove(&ip);
+        if let Some(ref lease) = lease {
+            self.unindex(lease);
+        }
+        Ok(lease)
+    }
+
+    fn leases(&self) -> Vec<Lease> {
+        self.leases.values().cloned().collect()
+    }
+
+    fn last_address(&self, client: &ClientId) -> Option<u32> {
+        self.history.get(client).map(|&(_, ip)| ip)
+    }
+
+    fn remember(&mut self, client: &ClientId, ip: u32) -> io::Result<()> {
+        if let Some((seq, _)) = self.history.remove(client) {
+            self.history_order.remove(&seq);
+        }
+        self.history_seq += 1;
+        self.history.insert(client.clone(), (self.history_seq, ip));
+        self.history_order.insert(self.history_seq, client.clone());
+
+        while self.history.len() > self.history_size {
+            let oldest = match self.history_order.keys().next() {
+                Some(&seq) => seq,
+                None => break,
+            };
+            if let Some(client) = self.history_order.remove(&oldest) {
+                self.history.remove(&client);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Dopisanych wpisów ponad liczbę dzierżaw, po których zastępujemy dziennik bieżącym stanem
+const COMPACT_SLACK: usize = 1000;
+
+/// Dzierżawy w pamięci, każda zmiana dopisywana do dziennika JSON. Gdy dziennik urośnie, zastępujemy go bieżącym stanem.
+pub struct JsonFileStore {
+    memory: MemoryStore,
+    journal: LeaseJournal,
+}
+
+fn lease_record(lease: &Lease) -> JournalEntry {
+    JournalEntry::Lease {
+        ip: Ipv4Addr::from(lease.ip),
+        client: lease.client.to_string(),
+        status: lease.status,
+        issued: lease.issued_at.map(unix_secs),
+        expires: lease.expires_at.map(unix_secs),
+        expired: lease.expired_at.map(unix_secs),
+        seen: lease.last_seen.map(unix_secs),
+    }
+}
+
+impl JsonFileStore {
+    /// Otwarcie dziennika i odtworzenie z niego stanu
+    pub fn open(path: &str, history_size: usize) -> io::Result<Self> {
+        let (journal, records) = LeaseJournal::open(path)?;
+        let mut memory = MemoryStore::new(history_size);
+
+        for record in records {
+            match record {
+                JournalEntry::Lease { ip, client, status, issued, expires, expired, seen } => {
+                    if let Ok(client) = client.parse::<ClientId>() {
+                        memory.insert(Lease {
+                            ip: u32::from(ip),
+                            client: client,
+                            status: status,
+                            issued_at: issued.map(from_unix_secs),
+                            expires_at: expires.map(from_unix_secs),
+                            expired_at: expired.map(from_unix_secs),
+                            last_seen: seen.map(from_unix_secs),
+                        })?;
+                    }
+                },
+                JournalEntry::Free { ip } => { memory.expire(u32::from(ip))?; },
+                JournalEntry::History { ip, client } => {
+                    if let Ok(client) = client.parse::<ClientId>() {
+                        memory.remember(&client, u32::from(ip))?;
+                    }
+                },
+            }
+        }
+
+        let mut store = JsonFileStore { memory: memory, journal: journal };
+        store.compact();
+        Ok(store)
+    }
+
+    /// Dopisanie wpisu do dziennika, zanim zmienimy stan w pamięci - po błędzie oba zostają bez zmiany
+    fn append(&mut self, record: JournalEntry) -> io::Result<()> {
+        self.journal.append(&record)?;
+        if self.journal.appended() > self.memory.leases.len() + COMPACT_SLACK {
+            self.compact();
+        }
+        Ok(())
+    }
+
+    /// Zastąpienie dziennika bieżącymi wpisami i historią klientów. Historię zapisujemy od najdawniejszej, żeby po
+    /// ponownym otwarciu limit zapominał tych samych klientów.
+    fn compact(&mut self) {
+        let memory = &self.memory;
+        let mut records: Vec<JournalEntry> = memory.leases.values().map(lease_record).collect();
+        records.extend(memory.history_order.values().map(|client| JournalEntry::History {
+            ip: Ipv4Addr::from(memory.history[client].1),
+            client: client.to_string(),
+        }));
+
+        if let Err(e) = self.journal.compact(&records) {
+            println!("Couldn't compact lease journal: {}", e);
+        }
+    }
+}
+
+impl LeaseStore for JsonFileStore {
+    fn get(&self, ip: u32) -> Option<Lease> {
+        self.memory.get(ip)
+    }
+
+    fn by_client(&self, client: &ClientId) -> Vec<Lease> {
+        self.memory.by_client(client)
+    }
+
+    /// Oferty serwer po restarcie i tak odrzuca, więc trzymamy je tylko w pamięci - bez zapisu na dysk przy każdym DHCPDISCOVER
+    fn insert(&mut self, lease: Lease) -> io::Result<Option<Lease>> {
+        if lease.status != Status::Offered {
+            self.append(lease_record(&lease))?;
+        }
+        self.memory.insert(lease)
+    }
+
+    /// Tak samo samą zmianę ostatniego kontaktu z klientem. Trafi do dziennika z następną zmianą wpisu.
+    fn update(&mut self, lease: Lease) -> io::Result<bool> {
+        let seen_only = match self.memory.get(lease.ip) {
+            Some(current) => Lease { last_seen: lease.last_seen, ..current } == lease,
+            None => return Ok(false),
+        };
+        if seen_only {
+            self.memory.update(lease)
+        } else {
+            self.insert(lease)?;
+            Ok(true)
+        }
+    }
+
+    fn expire(&mut self, ip: u32) -> io::Result<Option<Lease>> {
+        if !self.memory.contains(ip) { return Ok(None); }
+        self.append(JournalEntry::Free { ip: Ipv4Addr::from(ip) })?;
+        self.memory.expire(ip)
+    }
+
+    fn leases(&self) -> Vec<Lease> {
+        self.memory.leases()
+    }
+
+    fn last_address(&self, client: &ClientId) -> Option<u32> {
+        self.memory.last_address(client)
+    }
+
+    fn remember(&mut self, client: &ClientId, ip: u32) -> io::Result<()> {
+        self.append(JournalEntry::History { ip: Ipv4Addr::from(ip), client: client.to_string() })?;
+        self.memory.remember(client, ip)
+    }
+}
+
+
+#[cfg(test)]
+fn test_lease(ip: u32, client: u8, status: Status) -> Lease {
+    Lease {
+        ip: ip,
+        client: ClientId::Identifier(vec![client]),
+        status: status,
+        issued_at: Some(from_unix_secs(1_599_999_700)),
+        expires_at: Some(from_unix_secs(1_600_000_000)),
+        expired_at: None,
+        last_seen: Some(from_unix_secs(1_599_999_800)),
+    }
+}
+
+/// Każdy magazyn zachowuje się tak samo, a trwałe pamiętają stan po ponownym otwarciu
+#[test]
+fn lease_store_test() {
+    use std::fs;
+    use std::process;
+
+    fn exercise(store: &mut dyn LeaseStore) {
+        assert_eq!(store.insert(test_lease(10, 1, Status::Offered)).unwrap(), None);
+        assert_eq!(store.insert(test_lease(11, 2, Status::Leasing)).unwrap(), None);
+        assert!(store.update(test_lease(10, 1, Status::Leasing)).unwrap());
+        assert!(!store.update(test_lease(12, 1, Status::Leasing)).unwrap());
+
+        assert_eq!(store.get(10).unwrap().status, Status::Leasing);
+        assert_eq!(store.by_client(&ClientId::Identifier(vec![2])), vec![test_lease(11, 2, Status::Leasing)]);
+
+        // Adres przejęty przez innego klienta znika z wpisów poprzedniego
+        store.insert(test_lease(12, 3, Status::Offered)).unwrap();
+        store.insert(test_lease(12, 4, Status::Leasing)).unwrap();
+        assert!(store.by_client(&ClientId::Identifier(vec![3])).is_empty());
+        assert_eq!(store.by_client(&ClientId::Identifier(vec![4])), vec![test_lease(12, 4, Status::Leasing)]);
+        store.expire(12).unwrap();
+        assert!(store.by_client(&ClientId::Identifier(vec![4])).is_empty());
+
+        assert_eq!(store.expire(11).unwrap().map(|lease| lease.ip), Some(11));
+        assert!(!store.contains(11));
+        assert_eq!(store.leases().len(), 1);
+
+        // Przy limicie dwóch klientów zapominamy tego, którego obsłużyliśmy najdawniej
+        store.remember(&ClientId::Identifier(vec![1]), 10).unwrap();
+        store.remember(&ClientId::Identifier(vec![2]), 11).unwrap();
+        store.remember(&ClientId::Identifier(vec![3]), 12).unwrap();
+        assert_eq!(store.last_address(&ClientId::Identifier(vec![1])), None);
+        store.remember(&ClientId::Identifier(vec![2]), 11).unwrap();
+        store.remember(&ClientId::Identifier(vec![5]), 13).unwrap();
+        assert_eq!(store.last_address(&ClientId::Identifier(vec![3])), None);
+        assert_eq!(store.last_address(&ClientId::Identifier(vec![2])), Some(11));
+    }
+
+    exercise(&mut MemoryStore::new(2));
+
+    let dir = ::std::env::temp_dir();
+    let json_path = dir.join(format!("rust_dhcp_store_test_{}.journal", process::id())).to_str().unwrap().to_string();
+    let sqlite_path = dir.join(format!("rust_dhcp_store_test_{}.sqlite", process::id())).to_str().unwrap().to_string();
+    let _ = fs::remove_file(&json_path);
+    let _ = fs::remove_file(&sqlite_path);
+
+    exercise(&mut JsonFileStore::open(&json_path, 2).unwrap());
+    exercise(&mut SqliteStore::open(&sqlite_path, 2).unwrap());
+
+    let reopened: Vec<Box<dyn LeaseStore>> = vec![
+        Box::new(JsonFileStore::open(&json_path, 2).unwrap()),
+        Box::new(SqliteStore::open(&sqlite_path, 2).unwrap()),
+    ];
+    for store in reopened {
+        assert_eq!(store.leases(), vec![test_lease(10, 1, Status::Leasing)]);
+        assert_eq!(store.last_address(&ClientId::Identifier(vec![2])), Some(11));
+        assert_eq!(store.last_address(&ClientId::Identifier(vec![5])), Some(13));
+        assert_eq!(store.last_address(&ClientId::Identifier(vec![3])), None);
+    }
+
+    fs::remove_file(&json_path).unwrap();
+    fs::remove_file(&sqlite_path).unwrap();
+}
+
+/// Oferty i sam czas ostatniego kontaktu nie trafiają do dziennika
+#[test]
+fn journal_skips_offers_test() {
+    use std::fs;
+    use std::process;
+
+    let path = ::std::env::temp_dir().join(format!("rust_dhcp_offers_test_{}.journal", process::id())).to_str().unwrap().to_string();
+    let _ = fs::remove_file(&path);
+    let seen = |lease: Lease| Lease { last_seen: Some(from_unix_secs(1_599_999_900)), ..lease };
+
+    {
+        let mut store = JsonFileStore::open(&path, 16).unwrap();
+        store.insert(test_lease(10, 1, Status::Offered)).unwrap();
+        assert!(store.update(seen(test_lease(10, 1, Status::Offered))).unwrap());
+        assert_eq!(store.journal.appended(), 0);
+
+        store.insert(test_lease(10, 1, Status::Leasing)).unwrap();
+        assert!(store.update(seen(test_lease(10, 1, Status::Leasing))).unwrap());
+        assert_eq!(store.journal.appended(), 1);
+        assert_eq!(store.get(10), Some(seen(test_lease(10, 1, Status::Leasing))));
+    }
+
+    let store = JsonFileStore::open(&path, 16).unwrap();
+    assert_eq!(store.leases(), vec![test_lease(10, 1, Status::Leasing)]);
+    fs::remove_file(&path).unwrap();
+}
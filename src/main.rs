@@ -1,4 +1,4 @@
-#![feature(int_to_from_bytes)]
+#![cfg_attr(test, feature(test))]
 
 extern crate byteorder;
 extern crate bytes;
@@ -11,11 +11,21 @@ extern crate actix;
 #[macro_use] extern crate actix_derive;
 
 extern crate libc;
-extern crate hwaddr;
+extern crate rand;
+#[macro_use] extern crate rusqlite;
+#[cfg(test)] extern crate test;
 
 mod dhcp_frames;
 mod dhcp_options;
+mod hw_address;
+mod client_id;
 mod config;
+mod iface;
+mod allocator;
+mod lease_journal;
+mod lease_store;
+mod sqlite_store;
+mod clock;
 
 mod server_actor;
 mod io_actor;
@@ -26,9 +36,12 @@ use std::io::prelude::*;
 use config::*;
 use std::net::{UdpSocket, SocketAddr, IpAddr, Ipv4Addr};
 use actix::prelude::*;
-use io_actor::OutputActor;
+use io_actor::{OutputActor, IncomingPacket};
 use server_actor::ServerActor;
+use lease_store::open_store;
+use clock::SystemClock;
 use dhcp_frames::DHCPPacket;
+use iface::{enable_pktinfo, recv_with_destination};
 use std::os::unix::io::AsRawFd;
 use std::ffi::CString;
 use libc::c_void;
@@ -69,22 +82,39 @@ fn main() {
     let socket = UdpSocket::bind(SocketAddr::new(IpAddr::from(Ipv4Addr::from([0,0,0,0])), 67)).expect("Couldn't bind a socket");
     unsafe { set_socket_device(&socket, config.interface.as_str()); }
     socket.set_broadcast(true).expect("Couldn't set socket to bcast");
+    // Adres docelowy datagramu odróżnia odnowienie dzierżawy wysłane do nas od broadcastu w stanie REBINDING
+    enable_pktinfo(&socket).expect("Couldn't enable IP_PKTINFO on the socket");
     let input_socket = socket.try_clone().expect("Couldn't clone the socket");
 
     // Aktor odpowiadający za wysyłanie wiadomości na socket
     let output_actor: Addr<Syn, _> = OutputActor::new(socket).start();
-    // Aktor obsługujący logikę serwera DHCP
-    let server_actor: Addr<Syn, _> = ServerActor::new(config, output_actor.clone()).start();
+    // Magazyn dzierżaw wybrany w konfiguracji i aktor obsługujący logikę serwera DHCP
+    let store = open_store(config.lease_store, config.lease_file.as_ref(), config.history_size);
+    let server_actor: Addr<Syn, _> = ServerActor::new(config, store, output_actor.clone().recipient(), Box::new(SystemClock)).start();
 
-    // Tworzymy wątek odbierający w tle pakiety (recv_from) i wysyłający je do aktora serwera.
+    // Tworzymy wątek odbierający w tle pakiety (recvmsg) i wysyłający je do aktora serwera razem z adresem docelowym.
+    // Błędy odbioru zapisujemy w logu, a uszkodzone pakiety liczymy i pomijamy - wątek działa dalej.
     let _input_thread_handle = thread::spawn(move || {
+        let mut bad_packets: u64 = 0;
         loop {
             println!("Creating buffer");
             let mut buf = vec![0u8; 1024];
-            let (_, addr) = input_socket.recv_from(&mut buf).unwrap();
+            let (len, addr, destination) = match recv_with_destination(&input_socket, &mut buf) {
+                Ok(received) => received,
+                Err(e) => {
+                    println!("Couldn't receive a frame: {}", e);
+                    continue;
+                },
+            };
+            buf.truncate(len);
             println!("Received frame from {}", addr);
-            let packet = DHCPPacket::from_vec(buf).unwrap();
-            server_actor.do_send(packet);
+            match DHCPPacket::from_vec(buf) {
+                Ok(packet) => server_actor.do_send(IncomingPacket { packet: packet, destination: destination }),
+                Err(e) => {
+                    bad_packets += 1;
+                    println!("Dropping malformed frame from {}: {} ({} bad frames so far)", addr, e, bad_packets);
+                }
+            }
         }
     });
 
@@ -1,79 +1,208 @@
 use actix::prelude::*;
-use byteorder::{NetworkEndian, ReadBytesExt};
 use config::Config;
-use dhcp_frames::DHCPPacket;
+use allocator::Allocator;
+use dhcp_frames::{DHCPPacket, DHCPHeader, MIN_MAX_MESSAGE_SIZE};
 use dhcp_options::*;
-use io_actor::OutputActor;
-use std::collections::HashMap;
-use std::iter::Cycle;
-use std::ops::Range;
-use std::time::Duration;
+use client_id::ClientId;
+use io_actor::{IncomingPacket, OutgoingPacket};
+use clock::Clock;
+#[cfg(test)]
+use clock::{ManualClock, SystemClock};
+#[cfg(test)]
+use lease_store::MemoryStore;
+#[cfg(test)]
+use std::cell::Cell;
+#[cfg(test)]
+use std::rc::Rc;
+use lease_store::{Lease, LeaseStore, Status};
+#[cfg(test)]
+use lease_store::open_store;
+use std::collections::{BTreeSet, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::Ipv4Addr;
+use std::time::{Duration, SystemTime};
 use std::u32;
 
 
-/// Stan danego adresu IP serwera. Odpowiednio, dzierżawiony, zarezerwowany dla przydzielania statycznego, wygasły, odrzucony przez DHCPDECLINE
-#[derive(PartialEq)]
-pub enum Status {
-    Leasing,
-    Reserved,
-    Expiring,
-    Declined,
+// Co ile sekund sprawdzamy, którym adresom upłynął stan
+const SWEEP_INTERVAL: u64 = 1;
+
+// Co ile sekund zapisujemy statystyki serwera w logu
+const REPORT_INTERVAL: u64 = 60;
+
+/// Statystyki serwera: wolne adresy puli dynamicznej i liczba DHCPDISCOVER, na które zabrakło adresu
+pub struct ServerStats {
+    pub free_addresses: u32,
+    pub pool_exhausted: u64,
 }
 
-// Dla danaego adresu IP przechowujemy odpowiadający mu adres MAC, stan, oraz uchwyt na future, które można anulować.
-struct MapEntry {
-    status: Status,
-    hwaddr: u64,
-    spawn_handle: Option<SpawnHandle>,
+/// Zapytanie o statystyki serwera, w działającym serwerze trafiają one do logu co REPORT_INTERVAL
+#[cfg(test)]
+#[derive(Message)]
+#[rtype(ServerStats)]
+pub struct GetStats;
+
+/// Zapytanie o wszystkie wpisy magazynu dzierżaw, razem z ich czasami. Poza testami czasy dzierżaw odczytuje się
+/// wprost z dziennika lub bazy.
+#[cfg(test)]
+pub struct GetLeases;
+
+// Derive nie radzi sobie z typem generycznym w rtype
+#[cfg(test)]
+impl Message for GetLeases {
+    type Result = Vec<Lease>;
 }
 
-// Stan aktora serwera. Mapa dzierżaw, konfiguracja, iterator dla puli adresów, adres aktora odpowiadającego za wysyłanie.
+// Stan aktora serwera. Magazyn dzierżaw, indeks adresów według czasu upływu stanu - przegląda go jeden okresowy sweep,
+// konfiguracja, mapy bitowe wolnych adresów puli dynamicznej i puli BOOTP, adres aktora odpowiadającego za wysyłanie, zegar, licznik DHCPDISCOVER
+// bez wolnego adresu.
 pub struct ServerActor {
-    lease_map: HashMap<u32, MapEntry>,
-    static_map: HashMap<u64, u32>,
+    leases: Box<dyn LeaseStore>,
+    expiry: BTreeSet<(SystemTime, u32)>,
+    static_map: HashMap<ClientId, u32>,
     conf: Config,
-    pool_iter: Cycle<Range<u32>>,
-    output_actor: Addr<Syn, OutputActor>,
+    allocator: Allocator,
+    bootp_allocator: Option<Allocator>,
+    output_actor: Recipient<Syn, OutgoingPacket>,
+    clock: Box<dyn Clock>,
+    pool_exhausted: u64,
+}
+
+/// Zgłoszenie nieudanego zapisu do magazynu dzierżaw. Magazyn i indeksy serwera zostają wtedy w poprzednim stanie.
+fn logged<T>(result: io::Result<T>) -> Option<T> {
+    result.map_err(|e| println!("Couldn't write lease store: {}", e)).ok()
+}
+
+/// Adresy, których nie dostanie żaden klient: brama, identyfikator serwera, adres sieci i adres rozgłoszeniowy
+fn server_addresses(config: &Config) -> [u32; 4] {
+    let network = config.gateway & config.pool_mask;
+    [config.gateway, config.server_id, network, network | !config.pool_mask]
+}
+
+/// Mapa bitowa puli BOOTP, jeśli jest skonfigurowana. Tak jak w puli dynamicznej pomijamy wykluczenia i adresy serwera,
+/// a część przedziału spoza podsieci odcinamy od razu.
+fn bootp_allocator(config: &Config) -> Option<Allocator> {
+    let range = config.bootp_range.as_ref()?;
+    let network = config.gateway & config.pool_mask;
+    let broadcast = network | !config.pool_mask;
+
+    // Adresu rozgłoszeniowego i tak nie przydzielamy, więc podsieć kończymy przed nim
+    let mut allocator = Allocator::new(&[range.start.max(network) .. range.end.min(broadcast)], config.allocation_strategy);
+    for range in &config.exclusions {
+        allocator.exclude_range(range);
+    }
+    for &ip in &server_addresses(config) {
+        allocator.exclude(ip);
+    }
+    Some(allocator)
 }
 
 impl ServerActor {
-    /// opcje dla wiadomość ACK i OFFER. Typ wiadomości, maska, router, ID serwera DHCP, czas dzierżawy
-    fn ack_options(&self, message_type: u8) -> HashMap<u8, Vec<u8>> {
-        let mut options = HashMap::new();
+    /// Opcja konfiguracji, którą serwer potrafi podać klientowi, o ile ją ma
+    fn config_option(&self, code: u8) -> Option<DhcpOption> {
+        match code {
+            SUBNET_MASK => Some(DhcpOption::SubnetMask(self.conf.pool_mask)),
+            ROUTER => Some(DhcpOption::Router(vec![self.conf.gateway])),
+            DOMAIN_NAME_SERVER if !self.conf.dns.is_empty() => Some(DhcpOption::DomainNameServer(self.conf.dns.clone())),
+            BROADCAST_ADDRESS => Some(DhcpOption::BroadcastAddress(self.conf.gateway | !self.conf.pool_mask)),
+            TFTP_SERVER_NAME => self.conf.server_name.clone().map(DhcpOption::TftpServerName),
+            BOOTFILE_NAME => self.conf.boot_file.clone().map(DhcpOption::BootfileName),
+            _ => None,
+        }
+    }
+
+    /// opcje dla wiadomość ACK i OFFER. Typ wiadomości, opcje z listy klienta, ID serwera DHCP, czas dzierżawy, T1 i T2
+    fn ack_options(&self, message_type: u8, packet: &DHCPPacket) -> Vec<DhcpOption> {
+        let (renewal, rebinding) = self.renewal_times(&packet.client_id());
+        let mandatory = vec![
+            DhcpOption::ServerIdentifier(self.server_id()),
+            DhcpOption::IpAddressLeaseTime(self.conf.lease_time),
+            DhcpOption::RenewalTime(renewal),
+            DhcpOption::RebindingTime(rebinding),
+        ];
+        self.reply_options(message_type, packet, mandatory)
+    }
+
+    /// Czasy T1 i T2 w sekundach. Z rozrzutem T1 każdego klienta przesuwamy o stałą dla niego część czasu dzierżawy,
+    /// wyznaczoną z hasha identyfikatora, żeby klienci uruchomieni naraz nie odnawiali dzierżaw w tej samej chwili.
+    fn renewal_times(&self, client: &ClientId) -> (u32, u32) {
+        let lease_time = self.conf.lease_time as f64;
+        let mut renewal_ratio = self.conf.renewal_ratio;
+        if self.conf.renewal_jitter > 0.0 {
+            let mut hasher = DefaultHasher::new();
+            client.hash(&mut hasher);
+            let spread = (hasher.finish() % 2001) as f64 / 1000.0 - 1.0;
+            renewal_ratio += self.conf.renewal_jitter * spread;
+        }
+        ((lease_time * renewal_ratio) as u32, (lease_time * self.conf.rebinding_ratio) as u32)
+    }
 
+    /// opcje dla ACK na DHCPINFORM. Bez czasu dzierżawy (RFC 2131 3.4)
+    fn inform_options(&self, packet: &DHCPPacket) -> Vec<DhcpOption> {
+        self.reply_options(DHCP_ACK, packet, vec![DhcpOption::ServerIdentifier(self.server_id())])
+    }
+
+    /// Typ wiadomości na początku, potem opcje z listy klienta (opcja 55) w jego kolejności,
+    /// na końcu opcje obowiązkowe, jeśli klient o nie nie prosił. Klient bez listy dostaje maskę, router i DNSy.
+    fn reply_options(&self, message_type: u8, packet: &DHCPPacket, mandatory: Vec<DhcpOption>) -> Vec<DhcpOption> {
+        let default_list = [SUBNET_MASK, ROUTER, DOMAIN_NAME_SERVER];
+        let requested = packet.parameter_request_list().unwrap_or(&default_list);
 
+        let mut options = vec![DhcpOption::DhcpMessageType(message_type)];
 
-        options.insert(DHCP_MESSAGE_TYPE, vec![message_type]);
-        let netmask: u32 = self.conf.pool_mask.to_be();
-        options.insert(SUBNET_MASK, netmask.to_bytes().to_vec());
-        let router: u32 = self.conf.gateway.to_be();
-        options.insert(ROUTER, router.to_bytes().to_vec());
-        options.insert(DHCP_SERVER_IDENTIFIER, router.to_bytes().to_vec());
-        let lease_time: u32 = self.conf.lease_time.to_be();
-        options.insert(IP_ADDRESS_LEASE_TIME, lease_time.to_bytes().to_vec());
+        for &code in requested {
+            if options.iter().any(|option| option.code() == code) { continue; }
 
-        let dns: Vec<u8> = self.conf.dns.iter().flat_map(|addr| {addr.to_bytes().to_vec()}).collect();
-        options.insert(DOMAIN_NAME_SERVER, dns);
+            let option = match mandatory.iter().find(|option| option.code() == code) {
+                Some(option) => Some(option.clone()),
+                None => self.config_option(code),
+            };
+            if let Some(option) = option {
+                options.push(option);
+            }
+        }
+
+        for option in mandatory {
+            if !options.iter().any(|o| o.code() == option.code()) {
+                options.push(option);
+            }
+        }
 
         options
     }
 
-    /// opcje dla NAK. Tylko typ wiadomości.
-    fn nak_options(&self) -> HashMap<u8, Vec<u8>> {
-        let mut options = HashMap::new();
-        options.insert(DHCP_MESSAGE_TYPE, vec![DHCP_NAK]);
-        options
+    /// opcje dla NAK. Typ wiadomości i ID serwera, którego RFC 2131 (tabela 3) wymaga w każdym DHCPNAK.
+    fn nak_options(&self) -> Vec<DhcpOption> {
+        vec![DhcpOption::DhcpMessageType(DHCP_NAK), DhcpOption::ServerIdentifier(self.server_id())]
+    }
+
+    /// Ustawia sname i file odpowiedzi. Nie odsyłamy klientowi wartości z jego żądania.
+    fn set_boot_fields(&self, header: &mut DHCPHeader) {
+        header.sname = [0u8; 64];
+        header.file = [0u8; 128];
+        if let Some(ref server_name) = self.conf.server_name {
+            header.set_sname(server_name);
+        }
+        if let Some(ref boot_file) = self.conf.boot_file {
+            header.set_file(boot_file);
+        }
     }
 
     /// Ramka dla ACK lub OFFER
     fn ack_frame(&self, message_type: u8, packet: DHCPPacket, yiaddr: u32) -> DHCPPacket {
+        let options = self.ack_options(message_type, &packet);
         let mut header = packet.header;
         header.yiaddr = yiaddr;
-        header.siaddr = self.conf.gateway;
+        header.siaddr = self.conf.next_server;
         header.op = 0x02;
-        header.flags = 0x8000;
-
-        let options = self.ack_options(message_type);
+        // Klient bez adresu może nie odebrać unicastu, więc odpowiadamy na broadcast. Klientowi z ciaddr odpowiadamy
+        // unicastem i zostawiamy jego flagi.
+        if header.ciaddr == 0 {
+            header.flags = 0x8000;
+        }
+        self.set_boot_fields(&mut header);
 
         DHCPPacket { header, options }
     }
@@ -81,158 +210,538 @@ impl ServerActor {
     /// Ramka dla NAK
     fn nak_frame(&self, packet: DHCPPacket) -> DHCPPacket {
         let mut header = packet.header;
-        header.siaddr = self.conf.gateway;
+        header.siaddr = 0;
         header.op = 0x02;
         header.flags = 0x8000;
+        header.sname = [0u8; 64];
+        header.file = [0u8; 128];
 
         let options = self.nak_options();
         DHCPPacket { header, options }
     }
 
-    /// Adres IP wybrany przez klienta, o ile to możliwe. Jeśli nie, to następny z iteratora
-    fn get_new_ipaddr(&mut self, wanted_ip: Option<u32>, hwaddr: u64) -> u32 {
-        if let Some(ip) = self.static_map.get(&hwaddr) {
-            return *ip;
+    /// Ramka BOOTREPLY. Opcje jako rozszerzenia vendor (RFC 1497), bez typu wiadomości i czasu dzierżawy.
+    fn bootp_frame(&self, packet: DHCPPacket, yiaddr: u32) -> DHCPPacket {
+        let mut header = packet.header;
+        header.yiaddr = yiaddr;
+        header.siaddr = self.conf.next_server;
+        header.op = 0x02;
+        self.set_boot_fields(&mut header);
+
+        let options = [SUBNET_MASK, ROUTER, DOMAIN_NAME_SERVER].iter()
+            .filter_map(|&code| self.config_option(code))
+            .collect();
+
+        DHCPPacket { header, options }
+    }
+
+    /// Przekazanie odpowiedzi do aktora wysyłającego
+    fn send(&self, frame: DHCPPacket, max_size: u16) {
+        let _ = self.output_actor.do_send(OutgoingPacket { packet: frame, max_size: max_size, unicast: None });
+    }
+
+    /// Przekazanie odpowiedzi wysyłanej bezpośrednio na adres klienta
+    fn send_unicast(&self, frame: DHCPPacket, max_size: u16, ip: u32) {
+        let _ = self.output_actor.do_send(OutgoingPacket { packet: frame, max_size: max_size, unicast: Some(ip) });
+    }
+
+    /// Odpowiedź unicastem na ciaddr, jeśli klient ma już adres, a inaczej na broadcast (RFC 2131 4.1)
+    fn send_to_client(&self, frame: DHCPPacket, max_size: u16, ciaddr: u32) {
+        if ciaddr != 0 {
+            self.send_unicast(frame, max_size, ciaddr);
+        } else {
+            self.send(frame, max_size);
         }
+    }
 
-        let mut new_ip;
+    /// Statyczny przydział dla klienta. Rezerwacja po adresie sprzętowym pasuje tylko do klienta bez opcji 61 -
+    /// klony maszyn wirtualnych mają ten sam MAC, ale różne identyfikatory (RFC 2131 4.2).
+    fn static_ip(&self, packet: &DHCPPacket) -> Option<u32> {
+        self.static_map.get(&packet.client_id()).cloned()
+    }
 
-        match wanted_ip {
-            Some(ip) => new_ip = ip,
-            None => new_ip = self.pool_iter.next().unwrap(),
+    /// Czy adres można przydzielić klientowi: wolny albo już jego. Cudzy adres po wygaśnięciu dzierżawy czeka do końca
+    /// okresu po wygaśnięciu na powrót poprzedniego klienta. Adresów wyłączonych z puli nie przydzielamy nigdy.
+    fn is_available(&self, ip: u32, client: &ClientId) -> bool {
+        if self.allocator.is_excluded(ip) { return false; }
+
+        match self.leases.get(ip) {
+            None => true,
+            Some(ref lease) if lease.client == *client => lease.status != Status::Declined && lease.status != Status::Bootp,
+            Some(_) => false,
         }
+    }
 
-        while let Some(me) = self.lease_map.get(&new_ip) {
-            if me.status == Status::Expiring || me.hwaddr == hwaddr { break; }
-            new_ip = self.pool_iter.next().unwrap();
+    /// Czy adres podany przez klienta w ogóle nadaje się do przydzielenia: w naszej podsieci, w puli, nie wyłączony
+    /// i nie zarezerwowany statycznie dla kogoś innego
+    fn is_valid_candidate(&self, ip: u32, client: &ClientId) -> bool {
+        self.on_subnet(ip)
+            && self.in_pool(ip)
+            && self.conf.statics.get(&ip).map_or(true, |owner| owner == client)
+    }
+
+    /// Najdawniej wygasła dzierżawa - przy polityce odzyskiwania tę zabieramy, gdy w puli nie ma wolnych adresów.
+    /// Koniec okresu po wygaśnięciu to moment wygaśnięcia plus stały expiration_time, więc pierwsza wygasła dzierżawa
+    /// w indeksie czasów jest najdawniej wygasła. Czytamy z magazynu tylko wpisy przed nią.
+    fn oldest_expired(&self) -> Option<u32> {
+        self.expiry.iter()
+            .map(|&(_, ip)| ip)
+            .find(|&ip| self.leases.get(ip).map_or(false, |lease| lease.status == Status::Expiring))
+    }
+
+    fn stats(&self) -> ServerStats {
+        ServerStats {
+            free_addresses: self.allocator.free_count(),
+            pool_exhausted: self.pool_exhausted,
         }
+    }
 
-        new_ip
+    /// Czas bezwzględny za podaną liczbę sekund według zegara serwera
+    fn expires_in(&self, secs: u32) -> Option<SystemTime> {
+        Some(self.clock.now() + Duration::from_secs(secs as u64))
     }
 
-    /// Obsługa DHCPDISCOVER
-    fn handle_discover(&mut self, packet: DHCPPacket, ctx: &mut Context<Self>) {
-        println!("Handling discover");
-        let wanted_ip;
-        {
-            let wanted_ip_ = packet.options.get(&REQUESTED_IP_ADDRESS);
-            wanted_ip = match wanted_ip_ {
-                Some(ip) => ip.as_slice().read_u32::<NetworkEndian>().ok(),
-                None => None,
+    /// Wpis w magazynie dzierżaw, z aktualizacją mapy bitowej i indeksu czasów. Zwraca poprzedni wpis.
+    /// Gdy zapis się nie uda, mapa i indeks zostają bez zmian.
+    fn insert_entry(&mut self, lease: Lease) -> io::Result<Option<Lease>> {
+        let (ip, expires_at) = (lease.ip, lease.expires_at);
+        let prev_lease = self.leases.insert(lease)?;
+        self.unindex(&prev_lease);
+        if let Some(expires_at) = expires_at {
+            self.expiry.insert((expires_at, ip));
+        }
+        self.sync_allocator(ip);
+        Ok(prev_lease)
+    }
+
+    /// Usunięcie wpisu z magazynu dzierżaw, z aktualizacją mapy bitowej i indeksu czasów
+    fn remove_entry(&mut self, ip: u32) -> io::Result<Option<Lease>> {
+        let lease = self.leases.expire(ip)?;
+        self.unindex(&lease);
+        self.sync_allocator(ip);
+        Ok(lease)
+    }
+
+    /// Usunięcie zastąpionego albo usuniętego wpisu z indeksu czasów
+    fn unindex(&mut self, prev_lease: &Option<Lease>) {
+        if let Some(Lease { ip, expires_at: Some(expires_at), .. }) = *prev_lease {
+            self.expiry.remove(&(expires_at, ip));
+        }
+    }
+
+    /// Przejście do następnego stanu wszystkich adresów, których czas upłynął przed `now`. Oferta, okres po wygaśnięciu
+    /// i kwarantanna po DHCPDECLINE zwalniają adres, dzierżawa przechodzi w okres po wygaśnięciu. Czasy liczymy od
+    /// zapisanego upływu stanu, nie od chwili sweepu, więc opóźnienie sweepu czy przestój serwera ich nie przesuwają.
+    /// Adresy, których nowego stanu nie udało się zapisać, sprawdzamy ponownie przy następnym sweepie.
+    fn sweep(&mut self, now: SystemTime) {
+        let mut failed = Vec::new();
+        loop {
+            let (expires_at, ip) = match self.expiry.iter().next() {
+                Some(&key) if key.0 <= now => key,
+                _ => break,
             };
+            self.expiry.remove(&(expires_at, ip));
+
+            let lease = match self.leases.get(ip) {
+                Some(lease) => lease,
+                None => continue,
+            };
+            let result = match lease.status {
+                Status::Offered | Status::Expiring | Status::Declined => self.remove_entry(ip).map(|_| ()),
+                Status::Leasing => {
+                    self.insert_entry(Lease {
+                        status: Status::Expiring,
+                        expires_at: Some(expires_at + Duration::from_secs(self.conf.expiration_time as u64)),
+                        expired_at: Some(expires_at),
+                        ..lease
+                    }).map(|_| ())
+                },
+                Status::Reserved | Status::Bootp => Ok(()),
+            };
+            if logged(result).is_none() {
+                failed.push((expires_at, ip));
+            }
+        }
+        self.expiry.extend(failed);
+    }
+
+    /// Wpisy zastane w magazynie po restarcie. Oferty i rezerwacje z poprzedniego uruchomienia odrzucamy, pozostałe
+    /// trafiają do indeksu czasów - te, którym stan upłynął w czasie przestoju, przejdą dalej przy pierwszym sweepie.
+    fn restore_leases(&mut self) {
+        for lease in self.leases.leases() {
+            match lease.status {
+                Status::Offered | Status::Reserved => { logged(self.remove_entry(lease.ip)); },
+                _ => {
+                    if let Some(expires_at) = lease.expires_at {
+                        self.expiry.insert((expires_at, lease.ip));
+                    }
+                    self.sync_allocator(lease.ip);
+                },
+            }
+        }
+    }
+
+    /// Mapy bitowe zgodne z mapą dzierżaw. Adres z wygasłą dzierżawą też jest zajęty, więc nowi klienci dostają najpierw
+    /// adresy wolne, a wygasłe - tylko przy polityce odzyskiwania, gdy wolnych zabraknie.
+    fn sync_allocator(&mut self, ip: u32) {
+        let used = self.leases.contains(ip);
+        for allocator in Some(&mut self.allocator).into_iter().chain(self.bootp_allocator.as_mut()) {
+            if used {
+                allocator.reserve(ip);
+            } else {
+                allocator.release(ip);
+            }
         }
+    }
+
+    /// Adres zaoferowany lub dzierżawiony już klientowi, potem adres IP wybrany przez klienta, o ile to możliwe.
+    /// Jeśli nie, to adres, który klient miał poprzednio, a w ostateczności wolny adres z mapy bitowej według strategii.
+    /// None, gdy w puli nie ma już adresu do przydzielenia.
+    fn get_new_ipaddr(&mut self, wanted_ip: Option<u32>, packet: &DHCPPacket) -> Option<u32> {
+        if let Some(ip) = self.static_ip(packet) {
+            return Some(ip);
+        }
+
+        let client = packet.client_id();
+
+        // Klient ponawiający DHCPDISCOVER dostaje tę samą ofertę, a klient z dzierżawą - swój adres (RFC 2131 4.3.1)
+        let current = self.leases.by_client(&client).into_iter()
+            .find(|lease| lease.status == Status::Offered || lease.status == Status::Leasing);
+        if let Some(lease) = current {
+            return Some(lease.ip);
+        }
+
+        // Adres spoza puli lub podsieci pomijamy i przydzielamy zwykłym trybem
+        if let Some(ip) = wanted_ip {
+            if !self.is_valid_candidate(ip, &client) {
+                println!("{} asked for {} which we can't assign, picking another address", client, Ipv4Addr::from(ip));
+            } else if self.is_available(ip, &client) {
+                return Some(ip);
+            }
+        }
+
+        // Adres, który klient miał ostatnio, o ile nikt go w międzyczasie nie dostał
+        if let Some(ip) = self.leases.last_address(&client) {
+            if self.is_valid_candidate(ip, &client) && self.is_available(ip, &client) {
+                return Some(ip);
+            }
+        }
+
+        if let Some(ip) = self.allocator.next_free(&client) {
+            return Some(ip);
+        }
+
+        if self.conf.reclaim_oldest_expired {
+            return self.oldest_expired();
+        }
+
+        None
+    }
+
+    /// Adres z puli BOOTP - ten sam, który klient już dostał, albo wolny adres z mapy bitowej puli BOOTP według strategii
+    fn get_bootp_ipaddr(&mut self, client: &ClientId) -> Option<u32> {
+        let allocator = self.bootp_allocator.as_mut()?;
+
+        let bound = self.leases.by_client(client).into_iter()
+            .find(|lease| lease.status == Status::Bootp && allocator.contains(lease.ip) && !allocator.is_excluded(lease.ip))
+            .map(|lease| lease.ip);
+
+        bound.or_else(|| allocator.next_free(client))
+    }
 
-        let new_ip = self.get_new_ipaddr(wanted_ip, packet.header.chaddr);
+    /// Obsługa BOOTREQUEST bez typu wiadomości DHCP (RFC 951). Adres ze statycznej rezerwacji albo z puli BOOTP,
+    /// na czas nieograniczony. Gdy nie ma ani rezerwacji, ani wolnego adresu w puli BOOTP, nie odpowiadamy.
+    fn handle_bootp(&mut self, packet: DHCPPacket, _ctx: &mut Context<Self>) {
+        let client = packet.client_id();
 
-        // Po ustalonym czasie wysyłamy do siebie wiadomość o wygaśnięciu dzierżawy. Można anulować mając uchwyt.
-        let spawn_handle = ctx.notify_later::<StatusMessage>(StatusMessage(Status::Expiring, new_ip), Duration::from_secs(self.conf.expiration_time as u64));
-        let hwaddr = packet.header.chaddr;
-        let status = Status::Expiring;
-        let entry = MapEntry {
-            status: status,
-            spawn_handle: Some(spawn_handle),
-            hwaddr: hwaddr,
+        let ip = match self.static_ip(&packet) {
+            Some(ip) => ip,
+            None => match self.get_bootp_ipaddr(&client) {
+                Some(ip) => {
+                    let now = self.clock.now();
+                    let lease = Lease {
+                        ip: ip,
+                        client: client,
+                        status: Status::Bootp,
+                        issued_at: Some(now),
+                        expires_at: None,
+                        expired_at: None,
+                        last_seen: Some(now),
+                    };
+                    if logged(self.insert_entry(lease)).is_none() { return; }
+                    ip
+                },
+                None => {
+                    println!("No BOOTP address for {}", client);
+                    return;
+                },
+            },
         };
 
-        self.lease_map.insert(new_ip, entry);
+        let frame = self.bootp_frame(packet, ip);
+        self.send(frame, MIN_MAX_MESSAGE_SIZE);
+    }
+
+    /// Obsługa DHCPDISCOVER
+    fn handle_discover(&mut self, packet: DHCPPacket, _ctx: &mut Context<Self>) {
+        println!("Handling discover");
+        let wanted_ip = packet.requested_ip();
+
+        let new_ip = match self.get_new_ipaddr(wanted_ip, &packet) {
+            Some(ip) => ip,
+            None => {
+                self.pool_exhausted += 1;
+                println!("Address pool exhausted, not offering to {} ({} times so far)", packet.client_id(), self.pool_exhausted);
+                return;
+            },
+        };
 
+        // Adres statyczny ma już swój wpis w mapie, a trwającej dzierżawy klienta nie zamieniamy na ofertę
+        let client = packet.client_id();
+        let leased = self.leases.get(new_ip).map_or(false, |lease| lease.client == client && lease.status == Status::Leasing);
+        if leased {
+            self.touch(new_ip, &client);
+        } else if self.static_ip(&packet).is_none() {
+            // Ofertę trzymamy przez offer_time, potem adres wraca do puli. Niezapisanej oferty nie wysyłamy,
+            // bo ten sam adres mógłby dostać ktoś inny.
+            let lease = Lease {
+                ip: new_ip,
+                client: client,
+                status: Status::Offered,
+                issued_at: None,
+                expires_at: self.expires_in(self.conf.offer_time),
+                expired_at: None,
+                last_seen: Some(self.clock.now()),
+            };
+            if logged(self.insert_entry(lease)).is_none() { return; }
+        }
+
+        let max_size = packet.max_message_size();
         let frame = self.ack_frame(DHCP_OFFER, packet, new_ip);
         println!("Sending DHCPOFFER frame to output actor");
-        self.output_actor.do_send::<DHCPPacket>(frame);
+        self.send(frame, max_size);
     }
 
-    /// Obsługa DHCP_Request
-    fn handle_request(&mut self, packet: DHCPPacket, ctx: &mut Context<Self>) {
-        let wanted_ip;
-        {
-            let ciaddr = packet.header.ciaddr;
-            if ciaddr != 0 {
-                wanted_ip = Some(ciaddr);
-            } else {
-                let wanted_ip_ = packet.options.get(&REQUESTED_IP_ADDRESS);
-                wanted_ip = match wanted_ip_ {
-                    Some(ip) => ip.as_slice().read_u32::<NetworkEndian>().ok(),
-                    None => None,
-                };
-            }
+    /// Czy adres należy do puli dynamicznej i nie jest z niej wyłączony
+    fn in_pool(&self, ip: u32) -> bool {
+        self.allocator.contains(ip) && !self.allocator.is_excluded(ip)
+    }
+
+    /// Identyfikator serwera (opcja 54)
+    fn server_id(&self) -> u32 {
+        self.conf.server_id
+    }
+
+    /// Czy adres należy do obsługiwanej podsieci
+    fn on_subnet(&self, ip: u32) -> bool {
+        ip & self.conf.pool_mask == self.conf.gateway & self.conf.pool_mask
+    }
+
+    /// Adres, który klient ma u nas przydzielony - statycznie, zaoferowany albo dzierżawiony
+    fn client_ipaddr(&self, packet: &DHCPPacket) -> Option<u32> {
+        if let Some(ip) = self.static_ip(packet) {
+            return Some(ip);
         }
 
-        let new_ip = self.get_new_ipaddr(wanted_ip, packet.header.chaddr);
+        self.leases.by_client(&packet.client_id()).into_iter()
+            .find(|lease| lease.status == Status::Offered || lease.status == Status::Leasing || lease.status == Status::Expiring)
+            .map(|lease| lease.ip)
+    }
 
-        if let None = self.static_map.get(&packet.header.chaddr) {
-            let spawn_handle = ctx.notify_later::<StatusMessage>(StatusMessage(Status::Leasing, new_ip), Duration::from_secs(self.conf.lease_time as u64));
-            let hwaddr = packet.header.chaddr;
-            let status = Status::Leasing;
-            let entry = MapEntry {
-                status: status,
-                spawn_handle: Some(spawn_handle),
-                hwaddr: hwaddr,
-            };
+    /// Czy adres jest przydzielony klientowi - statycznie, jako oferta albo dzierżawa
+    fn holds(&self, ip: u32, packet: &DHCPPacket) -> bool {
+        if self.static_ip(packet) == Some(ip) {
+            return true;
+        }
+        let client = packet.client_id();
+        self.leases.get(ip).map_or(false, |lease| lease.client == client && lease.status != Status::Declined)
+    }
 
-            let prev_entry = self.lease_map.remove(&new_ip);
-            match prev_entry {
-                None => (),
-                Some(me) => match me.spawn_handle {
-                    Some(sh) => { ctx.cancel_future(sh); },
-                    None => (),
-                },
+    /// Odnotowanie, że klient dał znak życia w sprawie swojego adresu
+    fn touch(&mut self, ip: u32, client: &ClientId) {
+        if let Some(lease) = self.leases.get(ip) {
+            if lease.client == *client {
+                let lease = Lease { last_seen: Some(self.clock.now()), ..lease };
+                logged(self.leases.update(lease));
             }
+        }
+    }
 
-            self.lease_map.insert(new_ip, entry);
+    /// Zwolnienie adresu zaoferowanego klientowi, który nie przyjął oferty
+    fn release_offer(&mut self, client: &ClientId) {
+        let offered = self.leases.by_client(client).into_iter().filter(|lease| lease.status == Status::Offered);
+
+        for lease in offered {
+            logged(self.remove_entry(lease.ip));
         }
+    }
 
-        let frame = self.ack_frame(DHCP_ACK, packet, new_ip);
-        self.output_actor.do_send(frame);
+    /// Zatwierdzenie dzierżawy i wysłanie DHCPACK. Adresy statyczne nie mają czasu wygaśnięcia.
+    /// Dzierżawy, której nie udało się zapisać, nie potwierdzamy - klient ponowi DHCPREQUEST.
+    fn ack_lease(&mut self, ip: u32, packet: DHCPPacket) {
+        if let None = self.static_ip(&packet) {
+            let client = packet.client_id();
+            let now = self.clock.now();
+            let lease = Lease {
+                ip: ip,
+                client: client.clone(),
+                status: Status::Leasing,
+                issued_at: Some(now),
+                expires_at: self.expires_in(self.conf.lease_time),
+                expired_at: None,
+                last_seen: Some(now),
+            };
+            let written = self.leases.remember(&client, ip).and_then(|_| self.insert_entry(lease));
+            if let Err(e) = written {
+                println!("Couldn't write lease of {} for {}, not acknowledging: {}", Ipv4Addr::from(ip), client, e);
+                return;
+            }
+        }
+
+        let max_size = packet.max_message_size();
+        let ciaddr = packet.header.ciaddr;
+        let frame = self.ack_frame(DHCP_ACK, packet, ip);
+        self.send_to_client(frame, max_size, ciaddr);
     }
 
-    /// Do DHCPINFORM mamy politykę podobną do uczelnianej sieci - odrzucamy.
-    fn handle_inform(&self, packet: DHCPPacket, _ctx: &mut Context<Self>) {
+    /// Wysłanie DHCPNAK
+    fn nak(&self, packet: DHCPPacket) {
+        let max_size = packet.max_message_size();
         let frame = self.nak_frame(packet);
-        self.output_actor.do_send(frame);
+        self.send(frame, max_size);
+    }
+
+    /// Czy adres docelowy datagramu to broadcast, ograniczony albo naszej podsieci
+    fn is_broadcast(&self, ip: u32) -> bool {
+        ip == u32::MAX || ip == self.conf.gateway | !self.conf.pool_mask
     }
 
-    /// Obsługa DHCPRELEASE
-    fn handle_release(&mut self, packet: DHCPPacket, ctx: &mut Context<Self>) {
+    /// Obsługa DHCP_Request. Stan klienta rozpoznajemy według RFC 2131 4.3.2:
+    /// SELECTING - jest ID serwera i żądany adres; INIT-REBOOT - jest tylko żądany adres;
+    /// RENEWING i REBINDING - jest tylko ciaddr, w RENEWING wysłany unicastem do nas, w REBINDING na broadcast.
+    /// Gdy adresu docelowego nie znamy, zakładamy REBINDING.
+    fn handle_request(&mut self, packet: DHCPPacket, destination: Option<u32>, _ctx: &mut Context<Self>) {
+        let client = packet.client_id();
+        let ciaddr = packet.header.ciaddr;
+
+        match (packet.server_identifier(), packet.requested_ip(), ciaddr) {
+            (Some(server_id), Some(requested), 0) => {
+                // Klient wybrał ofertę innego serwera - zwalniamy naszą i nie odpowiadamy
+                if server_id != self.server_id() {
+                    println!("{} chose server {}, releasing our offer", client, Ipv4Addr::from(server_id));
+                    return self.release_offer(&client);
+                }
+
+                if self.holds(requested, &packet) {
+                    self.ack_lease(requested, packet);
+                } else {
+                    println!("DHCPREQUEST from {} for {} which was not offered, sending DHCPNAK", client, Ipv4Addr::from(requested));
+                    self.nak(packet);
+                }
+            },
+            (None, Some(requested), 0) => {
+                if !self.on_subnet(requested) {
+                    println!("DHCPREQUEST from {} for {} from another subnet, sending DHCPNAK", client, Ipv4Addr::from(requested));
+                    return self.nak(packet);
+                }
+
+                // Bez wiedzy o kliencie milczymy - może go obsługuje inny serwer
+                match self.client_ipaddr(&packet) {
+                    Some(ip) if ip == requested => self.ack_lease(requested, packet),
+                    Some(_) => self.nak(packet),
+                    None => println!("DHCPREQUEST from unknown client {}, ignoring", client),
+                }
+            },
+            (None, None, ciaddr) if ciaddr != 0 => {
+                let renewing = destination.map_or(false, |ip| !self.is_broadcast(ip));
+
+                if self.holds(ciaddr, &packet) {
+                    self.ack_lease(ciaddr, packet);
+                } else if self.leases.contains(ciaddr) {
+                    println!("DHCPREQUEST from {} extending {} which is not theirs, sending DHCPNAK", client, Ipv4Addr::from(ciaddr));
+                    self.nak(packet);
+                } else if !renewing {
+                    // REBINDING klienta, o którym nic nie wiemy - może go obsługuje inny serwer w tej sieci
+                    println!("DHCPREQUEST from unknown rebinding client {}, ignoring", client);
+                } else if self.is_valid_candidate(ciaddr, &client) {
+                    // Klient odnawia dzierżawę u nas, a wpisu nie ma, np. po restarcie serwera bez magazynu - przedłużamy
+                    self.ack_lease(ciaddr, packet);
+                } else {
+                    println!("DHCPREQUEST from {} renewing {} which we can't assign, sending DHCPNAK", client, Ipv4Addr::from(ciaddr));
+                    self.nak(packet);
+                }
+            },
+            _ => println!("Malformed DHCPREQUEST from {}, ignoring", client),
+        }
+    }
+
+    /// Obsługa DHCPINFORM. Klient ma już adres, podajemy mu tylko konfigurację: DHCPACK bez yiaddr i czasu dzierżawy,
+    /// wysłany bezpośrednio na ciaddr.
+    fn handle_inform(&mut self, packet: DHCPPacket, _ctx: &mut Context<Self>) {
+        let ciaddr = packet.header.ciaddr;
+        self.touch(ciaddr, &packet.client_id());
+        let max_size = packet.max_message_size();
+        let options = self.inform_options(&packet);
+
+        let mut header = packet.header;
+        header.yiaddr = 0;
+        header.siaddr = self.conf.next_server;
+        header.op = 0x02;
+        self.set_boot_fields(&mut header);
+        let frame = DHCPPacket { header, options };
+        self.send_to_client(frame, max_size, ciaddr);
+    }
+
+    /// Obsługa DHCPRELEASE. Zwalniamy tylko dzierżawę klienta, który ją wysłał.
+    fn handle_release(&mut self, packet: DHCPPacket, _ctx: &mut Context<Self>) {
         let rel_ip = packet.header.ciaddr;
-        let entry = self.lease_map.remove(&rel_ip);
-        match entry {
-            Some(MapEntry { ref status, ref spawn_handle, .. }) if status == &Status::Leasing => {
-                ctx.cancel_future(spawn_handle.unwrap());
-                //self.lease_map.remove(&rel_ip);
-            }
-            Some(_) => { self.lease_map.insert(rel_ip, entry.unwrap()); }
-            None => (),
+        match self.leases.get(rel_ip) {
+            Some(ref lease) if lease.status == Status::Leasing && lease.client == packet.client_id() => { logged(self.remove_entry(rel_ip)); },
+            _ => (),
         };
     }
 
-    /// Obsługa DHCPDECLINE
-    fn handle_decline(&mut self, packet: DHCPPacket, ctx: &mut Context<Self>) {
-        let decl_ip = packet.header.ciaddr;
-        let entry = self.lease_map.remove(&decl_ip);
-        match entry {
-            Some(MapEntry { ref status, ref spawn_handle, ref hwaddr }) if status == &Status::Leasing => {
-                ctx.cancel_future(spawn_handle.unwrap());
-                let new_spawn_handle = ctx.notify_later::<StatusMessage>(StatusMessage(Status::Expiring, decl_ip), Duration::from_secs(self.conf.expiration_time as u64));
-                let new_entry = MapEntry {
+    /// Obsługa DHCPDECLINE. Odrzucony adres klient podaje w opcji 50, ciaddr jest zerem (RFC 2131 tabela 5).
+    fn handle_decline(&mut self, packet: DHCPPacket, _ctx: &mut Context<Self>) {
+        let decl_ip = match packet.requested_ip() {
+            Some(ip) => ip,
+            None => return println!("DHCPDECLINE from {} without requested address, ignoring", packet.client_id()),
+        };
+        match self.leases.get(decl_ip) {
+            Some(lease) if lease.status == Status::Leasing && lease.client == packet.client_id() => {
+                let lease = Lease {
                     status: Status::Declined,
-                    hwaddr: *hwaddr,
-                    spawn_handle: Some(new_spawn_handle),
+                    expires_at: self.expires_in(self.conf.expiration_time),
+                    last_seen: Some(self.clock.now()),
+                    ..lease
                 };
-                self.lease_map.insert(decl_ip, new_entry);
-            }
-            Some(_) => { self.lease_map.insert(decl_ip, entry.unwrap()); }
-            None => ()
+                logged(self.insert_entry(lease));
+            },
+            _ => (),
         };
     }
 
-    pub fn new(config: Config, output_actor: Addr<Syn, OutputActor>) -> Self {
-        let pool_iter = config.pool_range.clone().cycle();
+    pub fn new(config: Config, leases: Box<dyn LeaseStore>, output_actor: Recipient<Syn, OutgoingPacket>, clock: Box<dyn Clock>) -> Self {
+        let mut allocator = Allocator::new(&config.pool_ranges, config.allocation_strategy);
+        for range in &config.exclusions {
+            allocator.exclude_range(range);
+        }
+        // Adresy, których nigdy nie przydzielamy, nawet gdy konfiguracja umieściła je w puli
+        for &ip in &server_addresses(&config) {
+            allocator.exclude(ip);
+        }
+        let bootp_allocator = bootp_allocator(&config);
+
         ServerActor {
-            lease_map: HashMap::new(),
+            leases: leases,
+            expiry: BTreeSet::new(),
             static_map: HashMap::new(),
             output_actor: output_actor,
+            clock: clock,
             conf: config,
-            pool_iter: pool_iter,
+            allocator: allocator,
+            bootp_allocator: bootp_allocator,
+            pool_exhausted: 0,
         }
     }
 }
@@ -240,37 +749,52 @@ impl ServerActor {
 impl Actor for ServerActor {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Context<Self>) {
-        for (ip,mac) in &self.conf.statics {
-            let entry = MapEntry {
-                hwaddr: *mac,
-                status: Status::Reserved,
-                spawn_handle: None,
-            };
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        self.restore_leases();
+        ctx.notify_later(Sweep, Duration::from_secs(SWEEP_INTERVAL));
+        ctx.notify_later(Report, Duration::from_secs(REPORT_INTERVAL));
 
-            self.lease_map.insert(*ip, entry);
-            self.static_map.insert(*mac, *ip);
+        // Rezerwacje statyczne mają pierwszeństwo przed dzierżawami sprzed restartu
+        let statics = self.conf.statics.clone();
+        for (ip, client) in statics {
+            if !self.on_subnet(ip) || server_addresses(&self.conf).contains(&ip) {
+                println!("Static address {} for {} is outside our subnet or belongs to the server, ignoring", Ipv4Addr::from(ip), client);
+                continue;
+            }
+
+            logged(self.insert_entry(Lease {
+                ip: ip,
+                client: client.clone(),
+                status: Status::Reserved,
+                issued_at: None,
+                expires_at: None,
+                expired_at: None,
+                last_seen: None,
+            }));
+            self.static_map.insert(client, ip);
         }
     }
 }
 
 
-impl Handler<DHCPPacket> for ServerActor {
+impl Handler<IncomingPacket> for ServerActor {
     type Result = ();
 
     /// Obsługa nadesłanych pakietów
-    fn handle(&mut self, msg: DHCPPacket, ctx: &mut Context<Self>) {
-        let dhcp_message_type;
-        {
-            let dhcp_message_type_ = msg.options.get(&DHCP_MESSAGE_TYPE);
-            dhcp_message_type = dhcp_message_type_.unwrap().as_slice().read_u8().unwrap();
-        }
+    fn handle(&mut self, incoming: IncomingPacket, ctx: &mut Context<Self>) {
+        let msg = incoming.packet;
+        if msg.header.op != 0x01 { return; }
+
+        let dhcp_message_type = match msg.message_type() {
+            Some(message_type) => message_type,
+            None => return self.handle_bootp(msg, ctx),
+        };
 
         println!("Got message with type {}", dhcp_message_type);
 
         match dhcp_message_type {
             DHCP_DISCOVER => self.handle_discover(msg, ctx),
-            DHCP_REQUEST => self.handle_request(msg, ctx),
+            DHCP_REQUEST => self.handle_request(msg, incoming.destination, ctx),
             DHCP_INFORM => self.handle_inform(msg, ctx),
             DHCP_DECLINE => self.handle_decline(msg, ctx),
             DHCP_RELEASE => self.handle_release(msg, ctx),
@@ -279,28 +803,667 @@ impl Handler<DHCPPacket> for ServerActor {
     }
 }
 
+#[cfg(test)]
+impl Handler<GetStats> for ServerActor {
+    type Result = MessageResult<GetStats>;
+
+    fn handle(&mut self, _msg: GetStats, _ctx: &mut Context<Self>) -> MessageResult<GetStats> {
+        MessageResult(self.stats())
+    }
+}
+
 #[derive(Message)]
-struct StatusMessage(Status, u32);
+struct Report;
 
-impl Handler<StatusMessage> for ServerActor {
+impl Handler<Report> for ServerActor {
     type Result = ();
 
-    /// Obsługa wiadomości wysyłanych do siebie, o wygasających adresach.
-    fn handle(&mut self, msg: StatusMessage, ctx: &mut Context<Self>) {
-        let status = msg.0;
-        let ip = msg.1;
+    /// Okresowy zapis statystyk w logu
+    fn handle(&mut self, _msg: Report, ctx: &mut Context<Self>) {
+        let stats = self.stats();
+        println!("{} free addresses in pool, pool exhausted {} times so far", stats.free_addresses, stats.pool_exhausted);
+        ctx.notify_later(Report, Duration::from_secs(REPORT_INTERVAL));
+    }
+}
 
-        match status {
-            Status::Expiring | Status::Declined => {
-                self.lease_map.remove(&ip);
-            },
-            Status::Leasing => {
-                let spawn_handle = ctx.notify_later::<StatusMessage>(StatusMessage(Status::Expiring, ip), Duration::from_secs(self.conf.expiration_time as u64));
-                let entry = self.lease_map.get_mut(&ip).unwrap();
-                entry.status = Status::Expiring;
-                entry.spawn_handle = Some(spawn_handle);
-            },
-            _ => ()
-        }
+#[cfg(test)]
+impl Handler<GetLeases> for ServerActor {
+    type Result = MessageResult<GetLeases>;
+
+    fn handle(&mut self, _msg: GetLeases, _ctx: &mut Context<Self>) -> MessageResult<GetLeases> {
+        MessageResult(self.leases.leases())
+    }
+}
+
+#[derive(Message)]
+struct Sweep;
+
+impl Handler<Sweep> for ServerActor {
+    type Result = ();
+
+    /// Okresowy przegląd adresów, którym upłynął stan - jeden timer dla wszystkich adresów
+    fn handle(&mut self, _msg: Sweep, ctx: &mut Context<Self>) {
+        let now = self.clock.now();
+        self.sweep(now);
+        ctx.notify_later(Sweep, Duration::from_secs(SWEEP_INTERVAL));
+    }
+}
+
+#[cfg(test)]
+fn test_config() -> Config {
+    use allocator::Strategy;
+    use lease_store::Backend;
+
+    Config {
+        pool_ranges: vec![0x0A_00_00_0A .. 0x0A_00_00_0B],
+        exclusions: Vec::new(),
+        pool_mask: 0xFF_FF_FF_00,
+        dns: vec![0x08_08_08_08],
+        gateway: 0x0A_00_00_01,
+        statics: HashMap::new(),
+        lease_time: 300,
+        expiration_time: 300,
+        interface: String::from("lo"),
+        server_name: None,
+        boot_file: None,
+        bootp_range: None,
+        server_id: 0x0A_00_00_02,
+        next_server: 0x0A_00_00_02,
+        reclaim_oldest_expired: false,
+        allocation_strategy: Strategy::Sequential,
+        offer_time: 60,
+        lease_file: None,
+        lease_store: Backend::Memory,
+        history_size: 16,
+        renewal_ratio: 0.5,
+        rebinding_ratio: 0.875,
+        renewal_jitter: 0.0,
+    }
+}
+
+#[cfg(test)]
+fn test_discover(client: u8) -> DHCPPacket {
+    test_message(client, DHCP_DISCOVER, 0, &[])
+}
+
+/// Wiadomość od klienta z podanym ciaddr i opcjami adresowymi, np. (REQUESTED_IP_ADDRESS, ip)
+#[cfg(test)]
+fn test_message(client: u8, message_type: u8, ciaddr: u32, options: &[(u8, u32)]) -> DHCPPacket {
+    use byteorder::{BigEndian, ByteOrder};
+
+    let mut raw = vec![0u8; 236];
+    raw[0] = 1;
+    raw[1] = 1;
+    raw[2] = 6;
+    BigEndian::write_u32(&mut raw[12..16], ciaddr);
+    raw[33] = client;
+    raw.extend_from_slice(&[0x63, 0x82, 0x53, 0x63, DHCP_MESSAGE_TYPE, 1, message_type]);
+    for &(code, ip) in options {
+        let mut option = [code, 4, 0, 0, 0, 0];
+        BigEndian::write_u32(&mut option[2..], ip);
+        raw.extend_from_slice(&option);
+    }
+    raw.push(END);
+    DHCPPacket::from_vec(raw).unwrap()
+}
+
+/// Serwer poza systemem aktorów, do sprawdzania pojedynczych metod. Odpowiedzi trafiają do kolektora, nie na socket.
+#[cfg(test)]
+fn test_server(config: Config) -> (::actix::SystemRunner, ServerActor) {
+    let system = System::new("test");
+    let replies: Addr<Syn, _> = Replies(Vec::new()).start();
+    let store = open_store(config.lease_store, config.lease_file.as_ref(), config.history_size);
+    (system, ServerActor::new(config, store, replies.recipient(), Box::new(SystemClock)))
+}
+
+/// Przy pełnej puli serwer nie odpowiada i zlicza takie zdarzenia
+#[test]
+fn pool_exhausted_test() {
+    let mut test = TestServer::new(test_config());
+
+    test.expect_reply(test_discover(1), DHCP_OFFER);
+    assert!(test.exchange(test_discover(2)).is_empty());
+    let stats = test.system.run_until_complete(test.server.send(GetStats)).unwrap();
+
+    assert_eq!(stats.pool_exhausted, 1);
+    assert_eq!(stats.free_addresses, 0);
+}
+
+/// Adresy spoza puli, spoza podsieci i cudze rezerwacje nie są przydzielane na życzenie klienta
+#[test]
+fn requested_address_validation_test() {
+    let mut config = test_config();
+    config.pool_ranges = vec![0x0A_00_00_01 .. 0x0A_00_00_20];
+    config.statics.insert(0x0A_00_00_15, ClientId::Identifier(vec![9]));
+    let (_system, mut server) = test_server(config);
+
+    let packet = test_discover(1);
+    let client = packet.client_id();
+    assert!(server.is_valid_candidate(0x0A_00_00_10, &client));
+    assert!(!server.is_valid_candidate(0x08_08_08_08, &client));
+    assert!(!server.is_valid_candidate(0x0A_00_00_50, &client));
+    assert!(!server.is_valid_candidate(0x0A_00_00_01, &client));
+    assert!(!server.is_valid_candidate(0x0A_00_00_02, &client));
+    assert!(!server.is_valid_candidate(0x0A_00_00_15, &client));
+    assert!(server.is_valid_candidate(0x0A_00_00_15, &ClientId::Identifier(vec![9])));
+
+    assert_eq!(server.get_new_ipaddr(Some(0x08_08_08_08), &packet), Some(0x0A_00_00_03));
+}
+
+/// Pula BOOTP pomija adresy serwera, wykluczenia i adresy spoza podsieci, a klient z adresem BOOTP dostaje go ponownie
+#[test]
+fn bootp_pool_test() {
+    let mut config = test_config();
+    config.bootp_range = Some(0x0A_00_00_00 .. 0x0A_00_00_05);
+    config.exclusions = vec![0x0A_00_00_03 .. 0x0A_00_00_04];
+    let (_system, mut server) = test_server(config);
+
+    let bootp_lease = |ip: u32, client: u8| Lease {
+        ip: ip,
+        client: ClientId::Identifier(vec![client]),
+        status: Status::Bootp,
+        issued_at: None,
+        expires_at: None,
+        expired_at: None,
+        last_seen: None,
+    };
+
+    let client = ClientId::Identifier(vec![1]);
+    assert_eq!(server.get_bootp_ipaddr(&client), Some(0x0A_00_00_04));
+    server.insert_entry(bootp_lease(0x0A_00_00_04, 1)).unwrap();
+    assert_eq!(server.get_bootp_ipaddr(&client), Some(0x0A_00_00_04));
+    assert_eq!(server.get_bootp_ipaddr(&ClientId::Identifier(vec![2])), None);
+
+    // Z przedziału wychodzącego poza podsieć zostaje tylko .254 - .255 to adres rozgłoszeniowy
+    let mut config = test_config();
+    config.bootp_range = Some(0x0A_00_00_FE .. 0x0A_00_01_02);
+    let (_system, mut server) = test_server(config);
+    assert_eq!(server.get_bootp_ipaddr(&client), Some(0x0A_00_00_FE));
+    server.insert_entry(bootp_lease(0x0A_00_00_FE, 1)).unwrap();
+    assert_eq!(server.get_bootp_ipaddr(&ClientId::Identifier(vec![2])), None);
+}
+
+/// Rezerwacja po MAC nie obejmuje klientów z tym samym MAC, którzy wysyłają własny identyfikator klienta
+#[test]
+fn static_hardware_reservation_test() {
+    let mut config = test_config();
+    config.statics.insert(0x0A_00_00_30, ClientId::Hardware(test_discover(1).header.hardware_address()));
+    let mut test = TestServer::new(config);
+
+    let mut clone = test_discover(1);
+    clone.options.push(DhcpOption::ClientIdentifier(vec![1, 2, 3]));
+    assert_eq!(test.expect_reply(test_discover(1), DHCP_OFFER), 0x0A_00_00_30);
+    assert_eq!(test.expect_reply(clone, DHCP_OFFER), 0x0A_00_00_0A);
+}
+
+/// Klient po wygaśnięciu dzierżawy dostaje poprzedni adres, o ile nikt inny go nie zajął
+#[test]
+fn sticky_address_test() {
+    let mut config = test_config();
+    config.pool_ranges = vec![0x0A_00_00_0A .. 0x0A_00_00_20];
+    let (_system, mut server) = test_server(config);
+
+    let packet = test_discover(1);
+    server.leases.remember(&packet.client_id(), 0x0A_00_00_15).unwrap();
+    assert_eq!(server.get_new_ipaddr(None, &packet), Some(0x0A_00_00_15));
+
+    server.insert_entry(Lease {
+        ip: 0x0A_00_00_15,
+        client: test_discover(2).client_id(),
+        status: Status::Leasing,
+        issued_at: None,
+        expires_at: None,
+        expired_at: None,
+        last_seen: None,
+    }).unwrap();
+    assert_eq!(server.get_new_ipaddr(None, &packet), Some(0x0A_00_00_0A));
+}
+
+/// Sweep przeprowadza dzierżawę przez okres po wygaśnięciu do zwolnienia adresu, z zapisem czasów.
+/// Przedłużona dzierżawa zostawia w indeksie tylko nowy czas.
+#[test]
+fn expiry_sweep_test() {
+    let (_system, mut server) = test_server(test_config());
+
+    let ip = 0x0A_00_00_0A;
+    let start = SystemTime::now();
+    let lease = Lease {
+        ip: ip,
+        client: test_discover(1).client_id(),
+        status: Status::Leasing,
+        issued_at: Some(start),
+        expires_at: Some(start + Duration::from_secs(10)),
+        expired_at: None,
+        last_seen: Some(start),
+    };
+    server.insert_entry(lease.clone()).unwrap();
+    server.insert_entry(Lease { expires_at: Some(start + Duration::from_secs(20)), ..lease }).unwrap();
+    assert_eq!(server.expiry.len(), 1);
+    assert!(!server.allocator.reserve(ip));
+
+    server.sweep(start + Duration::from_secs(15));
+    assert_eq!(server.leases.get(ip).unwrap().status, Status::Leasing);
+
+    // Sweep spóźniony o 5 sekund nie przesuwa momentu wygaśnięcia ani końca okresu po wygaśnięciu
+    let expired_at = start + Duration::from_secs(20);
+    let grace_end = expired_at + Duration::from_secs(server.conf.expiration_time as u64);
+    server.sweep(start + Duration::from_secs(25));
+    let lease = server.leases.get(ip).unwrap();
+    assert_eq!(lease.status, Status::Expiring);
+    assert_eq!(lease.expired_at, Some(expired_at));
+    assert_eq!(lease.expires_at, Some(grace_end));
+    assert_eq!(lease.issued_at, Some(start));
+
+    server.sweep(grace_end - Duration::from_secs(1));
+    assert!(server.leases.get(ip).is_some());
+    server.sweep(grace_end);
+    assert!(server.leases.get(ip).is_none());
+    assert!(server.expiry.is_empty());
+    assert!(server.allocator.reserve(ip));
+}
+
+/// Aktor zbierający odpowiedzi serwera zamiast wysyłać je na socket
+#[cfg(test)]
+struct Replies(Vec<OutgoingPacket>);
+
+#[cfg(test)]
+impl Actor for Replies {
+    type Context = Context<Self>;
+}
+
+#[cfg(test)]
+impl Handler<OutgoingPacket> for Replies {
+    type Result = ();
+
+    fn handle(&mut self, msg: OutgoingPacket, _ctx: &mut Context<Self>) {
+        self.0.push(msg);
+    }
+}
+
+#[cfg(test)]
+struct TakeReplies;
+
+#[cfg(test)]
+impl Message for TakeReplies {
+    type Result = Vec<OutgoingPacket>;
+}
+
+#[cfg(test)]
+impl Handler<TakeReplies> for Replies {
+    type Result = MessageResult<TakeReplies>;
+
+    fn handle(&mut self, _msg: TakeReplies, _ctx: &mut Context<Self>) -> MessageResult<TakeReplies> {
+        MessageResult(self.0.drain(..).collect())
+    }
+}
+
+/// Serwer z ręcznym zegarem. Odpowiedzi zbieramy po każdej wiadomości, czas przesuwamy bez czekania.
+#[cfg(test)]
+struct TestServer {
+    system: ::actix::SystemRunner,
+    server: Addr<Syn, ServerActor>,
+    replies: Addr<Syn, Replies>,
+    clock: ManualClock,
+}
+
+#[cfg(test)]
+impl TestServer {
+    fn new(config: Config) -> Self {
+        let store = open_store(config.lease_store, config.lease_file.as_ref(), config.history_size);
+        TestServer::with_store(config, store)
     }
-}
\ No newline at end of file
+
+    /// Serwer z podanym magazynem dzierżaw zamiast wybranego w konfiguracji
+    fn with_store(config: Config, store: Box<dyn LeaseStore>) -> Self {
+        let system = System::new("test");
+        let clock = ManualClock::new(SystemTime::now());
+        let replies: Addr<Syn, _> = Replies(Vec::new()).start();
+        let server: Addr<Syn, _> = ServerActor::new(config, store, replies.clone().recipient(), Box::new(clock.clone())).start();
+        TestServer { system: system, server: server, replies: replies, clock: clock }
+    }
+
+    /// Wysłanie wiadomości od klienta na broadcast i odpowiedzi serwera na nią
+    fn exchange(&mut self, packet: DHCPPacket) -> Vec<DHCPPacket> {
+        self.send_to(packet, u32::MAX)
+    }
+
+    /// Wysłanie wiadomości od klienta na podany adres i odpowiedzi serwera na nią
+    fn send_to(&mut self, packet: DHCPPacket, destination: u32) -> Vec<DHCPPacket> {
+        self.deliver(packet, destination).into_iter().map(|reply| reply.packet).collect()
+    }
+
+    /// Jak send_to, ale odpowiedzi razem z adresem, na który serwer je wysyła
+    fn deliver(&mut self, packet: DHCPPacket, destination: u32) -> Vec<OutgoingPacket> {
+        self.system.run_until_complete(self.server.send(IncomingPacket { packet: packet, destination: Some(destination) })).unwrap();
+        self.system.run_until_complete(self.replies.send(TakeReplies)).unwrap()
+    }
+
+    /// Jedna odpowiedź podanego typu, zwraca przydzielony adres
+    fn expect_reply(&mut self, packet: DHCPPacket, message_type: u8) -> u32 {
+        let replies = self.exchange(packet);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].message_type(), Some(message_type));
+        replies[0].header.yiaddr
+    }
+
+    /// Przesunięcie zegara i sweep, tak jakby upłynął podany czas
+    fn advance(&mut self, secs: u64) {
+        self.clock.advance(Duration::from_secs(secs));
+        self.system.run_until_complete(self.server.send(Sweep)).unwrap();
+    }
+
+    fn lease(&mut self, ip: u32) -> Option<Lease> {
+        let leases = self.system.run_until_complete(self.server.send(GetLeases)).unwrap();
+        leases.into_iter().find(|lease| lease.ip == ip)
+    }
+}
+
+/// Pełny cykl: DISCOVER, OFFER, REQUEST, ACK, przedłużenie, wygaśnięcie i oferta tego samego adresu innemu klientowi
+#[test]
+fn lease_lifecycle_test() {
+    let mut test = TestServer::new(test_config());
+    let server_id = test_config().server_id;
+
+    let ip = test.expect_reply(test_discover(1), DHCP_OFFER);
+    assert_eq!(ip, 0x0A_00_00_0A);
+    let request = test_message(1, DHCP_REQUEST, 0, &[(REQUESTED_IP_ADDRESS, ip), (DHCP_SERVER_IDENTIFIER, server_id)]);
+    assert_eq!(test.expect_reply(request, DHCP_ACK), ip);
+    let issued_at = test.clock.now();
+    assert_eq!(test.lease(ip).unwrap().expires_at, Some(issued_at + Duration::from_secs(300)));
+
+    // Przedłużenie w połowie dzierżawy przesuwa jej koniec
+    test.advance(150);
+    let replies = test.send_to(test_message(1, DHCP_REQUEST, ip, &[]), server_id);
+    assert_eq!(replies[0].message_type(), Some(DHCP_ACK));
+    assert_eq!(replies[0].header.yiaddr, ip);
+    test.advance(200);
+    assert_eq!(test.lease(ip).unwrap().status, Status::Leasing);
+
+    test.advance(100);
+    let lease = test.lease(ip).unwrap();
+    assert_eq!(lease.status, Status::Expiring);
+    assert_eq!(lease.issued_at, Some(issued_at + Duration::from_secs(150)));
+
+    // Przez okres po wygaśnięciu adres czeka na poprzedniego klienta, potem wraca do puli
+    assert!(test.exchange(test_discover(2)).is_empty());
+    test.advance(300);
+    assert_eq!(test.expect_reply(test_discover(2), DHCP_OFFER), ip);
+    assert_eq!(test.lease(ip).unwrap().client, test_discover(2).client_id());
+}
+
+/// Nowy klient dostaje najpierw adres nigdy nieprzydzielony, a wygasły - tylko przy polityce odzyskiwania i pełnej puli
+#[test]
+fn expired_address_reuse_test() {
+    let mut config = test_config();
+    config.pool_ranges = vec![0x0A_00_00_0A .. 0x0A_00_00_0C];
+    config.reclaim_oldest_expired = true;
+    let mut test = TestServer::new(config);
+    let server_id = test_config().server_id;
+
+    let ip = test.expect_reply(test_discover(1), DHCP_OFFER);
+    let request = test_message(1, DHCP_REQUEST, 0, &[(REQUESTED_IP_ADDRESS, ip), (DHCP_SERVER_IDENTIFIER, server_id)]);
+    test.expect_reply(request, DHCP_ACK);
+    test.advance(300);
+    assert_eq!(test.lease(ip).unwrap().status, Status::Expiring);
+
+    let fresh = test.expect_reply(test_discover(2), DHCP_OFFER);
+    assert!(fresh != ip);
+    assert_eq!(test.expect_reply(test_discover(3), DHCP_OFFER), ip);
+}
+
+/// Po restarcie serwer odtwarza dzierżawy z magazynu i nie przydziela ich innym, a oferty z poprzedniego uruchomienia odrzuca
+#[test]
+fn restart_test() {
+    use lease_store::Backend;
+    use std::fs;
+    use std::process;
+
+    for &(backend, extension) in &[(Backend::Json, "journal"), (Backend::Sqlite, "sqlite")] {
+        let path = ::std::env::temp_dir().join(format!("rust_dhcp_restart_test_{}.{}", process::id(), extension));
+        let path = path.to_str().unwrap().to_string();
+        let _ = fs::remove_file(&path);
+        let mut config = test_config();
+        config.pool_ranges = vec![0x0A_00_00_0A .. 0x0A_00_00_0C];
+        config.lease_store = backend;
+        config.lease_file = Some(path.clone());
+        let server_id = config.server_id;
+
+        let (ip, offered) = {
+            let mut test = TestServer::new(config.clone());
+            let ip = test.expect_reply(test_discover(1), DHCP_OFFER);
+            test.expect_reply(test_message(1, DHCP_REQUEST, 0, &[(REQUESTED_IP_ADDRESS, ip), (DHCP_SERVER_IDENTIFIER, server_id)]), DHCP_ACK);
+            (ip, test.expect_reply(test_discover(2), DHCP_OFFER))
+        };
+
+        let mut test = TestServer::new(config);
+        assert_eq!(test.lease(ip).unwrap().status, Status::Leasing);
+        assert!(test.lease(offered).is_none());
+        assert_eq!(test.expect_reply(test_discover(3), DHCP_OFFER), offered);
+        assert_eq!(test.send_to(test_message(1, DHCP_REQUEST, ip, &[]), server_id)[0].message_type(), Some(DHCP_ACK));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+/// Nieprzyjęta oferta wraca do puli po offer_time
+#[test]
+fn offer_timeout_test() {
+    let mut test = TestServer::new(test_config());
+
+    let ip = test.expect_reply(test_discover(1), DHCP_OFFER);
+    assert!(test.exchange(test_discover(2)).is_empty());
+    test.advance(59);
+    assert!(test.exchange(test_discover(2)).is_empty());
+    test.advance(1);
+    assert_eq!(test.expect_reply(test_discover(2), DHCP_OFFER), ip);
+}
+
+/// Adres odrzucony przez DHCPDECLINE jest w kwarantannie przez expiration_time
+#[test]
+fn decline_quarantine_test() {
+    let mut test = TestServer::new(test_config());
+    let server_id = test_config().server_id;
+
+    let ip = test.expect_reply(test_discover(1), DHCP_OFFER);
+    let request = test_message(1, DHCP_REQUEST, 0, &[(REQUESTED_IP_ADDRESS, ip), (DHCP_SERVER_IDENTIFIER, server_id)]);
+    test.expect_reply(request, DHCP_ACK);
+    let decline = test_message(1, DHCP_DECLINE, 0, &[(REQUESTED_IP_ADDRESS, ip), (DHCP_SERVER_IDENTIFIER, server_id)]);
+    assert!(test.exchange(decline).is_empty());
+    assert_eq!(test.lease(ip).unwrap().status, Status::Declined);
+
+    test.advance(299);
+    assert!(test.exchange(test_discover(2)).is_empty());
+    test.advance(1);
+    assert_eq!(test.expect_reply(test_discover(2), DHCP_OFFER), ip);
+}
+
+/// T1 i T2 trafiają do OFFER i ACK. Z rozrzutem T1 zostaje w zadanym przedziale, stałe dla klienta i różne między klientami.
+#[test]
+fn renewal_times_test() {
+    {
+        let mut test = TestServer::new(test_config());
+        let offer = test.exchange(test_discover(1)).pop().unwrap();
+        assert_eq!(offer.option(RENEWAL_TIME), Some(&DhcpOption::RenewalTime(150)));
+        assert_eq!(offer.option(REBINDING_TIME), Some(&DhcpOption::RebindingTime(262)));
+    }
+
+    let mut config = test_config();
+    config.renewal_jitter = 0.1;
+    let (_system, server) = test_server(config);
+    let client = test_discover(1).client_id();
+    assert_eq!(server.renewal_times(&client), server.renewal_times(&client));
+    assert_eq!(server.renewal_times(&client).1, 262);
+
+    let times: Vec<u32> = (1..50).map(|client| server.renewal_times(&test_discover(client).client_id()).0).collect();
+    assert!(times.iter().all(|&t| t >= 120 && t <= 180));
+    assert!(times.iter().any(|&t| t != times[0]));
+}
+
+/// Ponowiony DHCPDISCOVER nie zajmuje drugiego adresu - klient dostaje tę samą ofertę
+#[test]
+fn repeated_discover_test() {
+    let mut config = test_config();
+    config.pool_ranges = vec![0x0A_00_00_0A .. 0x0A_00_00_20];
+    let mut test = TestServer::new(config);
+
+    let ip = test.expect_reply(test_discover(1), DHCP_OFFER);
+    test.advance(5);
+    assert_eq!(test.expect_reply(test_discover(1), DHCP_OFFER), ip);
+
+    let leases = test.system.run_until_complete(test.server.send(GetLeases)).unwrap();
+    assert_eq!(leases.len(), 1);
+    assert_eq!(leases[0].status, Status::Offered);
+}
+
+/// DHCPREQUEST w każdym stanie klienta z RFC 2131 4.3.2: kiedy ACK, kiedy NAK, a kiedy milczymy
+#[test]
+fn request_states_test() {
+    let mut config = test_config();
+    config.pool_ranges = vec![0x0A_00_00_0A .. 0x0A_00_00_20];
+    let server_id = config.server_id;
+    let mut test = TestServer::new(config);
+    let ip = test.expect_reply(test_discover(1), DHCP_OFFER);
+
+    // SELECTING - ACK tylko dla zaoferowanego adresu
+    let selecting = |client, requested| test_message(client, DHCP_REQUEST, 0, &[(REQUESTED_IP_ADDRESS, requested), (DHCP_SERVER_IDENTIFIER, server_id)]);
+    assert_eq!(test.expect_reply(selecting(1, ip), DHCP_ACK), ip);
+    let nak = test.exchange(selecting(2, 0x0A_00_00_15)).pop().unwrap();
+    assert_eq!(nak.message_type(), Some(DHCP_NAK));
+    assert_eq!(nak.option(DHCP_SERVER_IDENTIFIER), Some(&DhcpOption::ServerIdentifier(server_id)));
+
+    // INIT-REBOOT - ACK dla własnego adresu, NAK dla cudzego i spoza podsieci, milczenie wobec nieznanego klienta
+    let init_reboot = |client, requested| test_message(client, DHCP_REQUEST, 0, &[(REQUESTED_IP_ADDRESS, requested)]);
+    assert_eq!(test.expect_reply(init_reboot(1, ip), DHCP_ACK), ip);
+    test.expect_reply(init_reboot(1, 0x0A_00_00_12), DHCP_NAK);
+    test.expect_reply(init_reboot(1, 0x0B_00_00_12), DHCP_NAK);
+    assert!(test.exchange(init_reboot(3, 0x0A_00_00_12)).is_empty());
+
+    // RENEWING - unicast do nas i DHCPACK unicastem na ciaddr; nieznany adres z puli przedłużamy, cudzy odrzucamy
+    let extending = |client, ciaddr| test_message(client, DHCP_REQUEST, ciaddr, &[]);
+    let ack = test.deliver(extending(1, ip), server_id).pop().unwrap();
+    assert_eq!(ack.packet.message_type(), Some(DHCP_ACK));
+    assert_eq!(ack.unicast, Some(ip));
+    assert_eq!(ack.packet.header.flags, 0);
+    assert_eq!(test.send_to(extending(4, 0x0A_00_00_11), server_id)[0].message_type(), Some(DHCP_ACK));
+    assert_eq!(test.send_to(extending(2, ip), server_id)[0].message_type(), Some(DHCP_NAK));
+
+    // REBINDING - broadcast; nieznanego klienta może obsługiwać inny serwer
+    test.expect_reply(extending(1, ip), DHCP_ACK);
+    assert!(test.exchange(extending(5, 0x0A_00_00_12)).is_empty());
+    test.expect_reply(extending(2, ip), DHCP_NAK);
+
+    // ciaddr razem z żądanym adresem nie pasuje do żadnego stanu
+    assert!(test.exchange(test_message(1, DHCP_REQUEST, ip, &[(REQUESTED_IP_ADDRESS, ip)])).is_empty());
+}
+
+/// DHCPREQUEST z ID innego serwera zwalnia naszą ofertę i zostaje bez odpowiedzi
+#[test]
+fn other_server_selected_test() {
+    let mut test = TestServer::new(test_config());
+    let ip = test.expect_reply(test_discover(1), DHCP_OFFER);
+
+    let request = test_message(1, DHCP_REQUEST, 0, &[(REQUESTED_IP_ADDRESS, 0x0A_00_00_63), (DHCP_SERVER_IDENTIFIER, 0x0A_00_00_03)]);
+    assert!(test.exchange(request).is_empty());
+    assert!(test.lease(ip).is_none());
+    assert_eq!(test.expect_reply(test_discover(2), DHCP_OFFER), ip);
+}
+
+/// Opcje odpowiedzi w kolejności z listy klienta, bez opcji, których nie mamy, a na końcu obowiązkowe
+#[test]
+fn parameter_request_order_test() {
+    let mut test = TestServer::new(test_config());
+    let mut discover = test_discover(1);
+    discover.options.push(DhcpOption::ParameterRequestList(vec![DOMAIN_NAME_SERVER, IP_ADDRESS_LEASE_TIME, ROUTER, 200, SUBNET_MASK, DHCP_MESSAGE_TYPE]));
+
+    let offer = test.exchange(discover).pop().unwrap();
+    let codes: Vec<u8> = offer.options.iter().map(|option| option.code()).collect();
+    assert_eq!(codes, vec![
+        DHCP_MESSAGE_TYPE, DOMAIN_NAME_SERVER, IP_ADDRESS_LEASE_TIME, ROUTER, SUBNET_MASK,
+        DHCP_SERVER_IDENTIFIER, RENEWAL_TIME, REBINDING_TIME,
+    ]);
+}
+
+/// DHCPINFORM dostaje DHCPACK unicastem na ciaddr, bez yiaddr i bez czasu dzierżawy
+#[test]
+fn inform_test() {
+    let mut test = TestServer::new(test_config());
+    let ciaddr = 0x0A_00_00_40;
+
+    let mut replies = test.deliver(test_message(1, DHCP_INFORM, ciaddr, &[]), u32::MAX);
+    assert_eq!(replies.len(), 1);
+    let reply = replies.pop().unwrap();
+    assert_eq!(reply.unicast, Some(ciaddr));
+    assert_eq!(reply.packet.message_type(), Some(DHCP_ACK));
+    assert_eq!(reply.packet.header.yiaddr, 0);
+    assert_eq!(reply.packet.option(IP_ADDRESS_LEASE_TIME), None);
+    assert_eq!(reply.packet.option(DHCP_SERVER_IDENTIFIER), Some(&DhcpOption::ServerIdentifier(0x0A_00_00_02)));
+}
+
+/// Magazyn w pamięci, którego zapisy przestają się udawać po ustawieniu flagi - jak baza zablokowana przez inny proces
+#[cfg(test)]
+struct FailingStore {
+    memory: MemoryStore,
+    failing: Rc<Cell<bool>>,
+}
+
+#[cfg(test)]
+impl FailingStore {
+    fn check(&self) -> io::Result<()> {
+        if self.failing.get() { Err(io::Error::new(io::ErrorKind::Other, "database is locked")) } else { Ok(()) }
+    }
+}
+
+#[cfg(test)]
+impl LeaseStore for FailingStore {
+    fn get(&self, ip: u32) -> Option<Lease> { self.memory.get(ip) }
+    fn by_client(&self, client: &ClientId) -> Vec<Lease> { self.memory.by_client(client) }
+    fn insert(&mut self, lease: Lease) -> io::Result<Option<Lease>> { self.check()?; self.memory.insert(lease) }
+    fn update(&mut self, lease: Lease) -> io::Result<bool> { self.check()?; self.memory.update(lease) }
+    fn expire(&mut self, ip: u32) -> io::Result<Option<Lease>> { self.check()?; self.memory.expire(ip) }
+    fn leases(&self) -> Vec<Lease> { self.memory.leases() }
+    fn last_address(&self, client: &ClientId) -> Option<u32> { self.memory.last_address(client) }
+    fn remember(&mut self, client: &ClientId, ip: u32) -> io::Result<()> { self.check()?; self.memory.remember(client, ip) }
+}
+
+/// Bez zapisu dzierżawy nie ma DHCPACK, a oferta, której nie udało się usunąć, wygasa przy kolejnym sweepie
+#[test]
+fn failed_write_test() {
+    let failing = Rc::new(Cell::new(false));
+    let store = FailingStore { memory: MemoryStore::new(16), failing: failing.clone() };
+    let mut test = TestServer::with_store(test_config(), Box::new(store));
+    let server_id = test_config().server_id;
+
+    let ip = test.expect_reply(test_discover(1), DHCP_OFFER);
+    let request = || test_message(1, DHCP_REQUEST, 0, &[(REQUESTED_IP_ADDRESS, ip), (DHCP_SERVER_IDENTIFIER, server_id)]);
+    failing.set(true);
+    assert!(test.exchange(request()).is_empty());
+    assert_eq!(test.lease(ip).unwrap().status, Status::Offered);
+
+    test.advance(60);
+    assert_eq!(test.lease(ip).unwrap().status, Status::Offered);
+    failing.set(false);
+    test.advance(1);
+    assert!(test.lease(ip).is_none());
+
+    assert_eq!(test.expect_reply(test_discover(1), DHCP_OFFER), ip);
+    assert_eq!(test.expect_reply(request(), DHCP_ACK), ip);
+}
+
+/// DHCPRELEASE z cudzym adresem w ciaddr nie zwalnia dzierżawy
+#[test]
+fn release_test() {
+    let mut config = test_config();
+    config.pool_ranges = vec![0x0A_00_00_0A .. 0x0A_00_00_0C];
+    let mut test = TestServer::new(config);
+    let server_id = test_config().server_id;
+
+    let ip = test.expect_reply(test_discover(1), DHCP_OFFER);
+    test.expect_reply(test_message(1, DHCP_REQUEST, 0, &[(REQUESTED_IP_ADDRESS, ip), (DHCP_SERVER_IDENTIFIER, server_id)]), DHCP_ACK);
+
+    assert!(test.send_to(test_message(2, DHCP_RELEASE, ip, &[(DHCP_SERVER_IDENTIFIER, server_id)]), server_id).is_empty());
+    assert_eq!(test.lease(ip).unwrap().status, Status::Leasing);
+    assert!(test.expect_reply(test_discover(2), DHCP_OFFER) != ip);
+
+    test.send_to(test_message(1, DHCP_RELEASE, ip, &[(DHCP_SERVER_IDENTIFIER, server_id)]), server_id);
+    assert!(test.lease(ip).is_none());
+}
@@ -0,0 +1,203 @@
+use client_id::ClientId;
+use lease_store::{Lease, LeaseStore, Status, unix_secs, from_unix_secs};
+use rusqlite::{self, Connection, OptionalExtension, Row};
+use std::io;
+use std::net::Ipv4Addr;
+use std::time::SystemTime;
+
+// Adresy zapisujemy jako tekst 192.168.0.1, a czasy jako sekundy od epoki Uniksa, żeby tabelę dało się czytać zwykłym sqlite3
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS leases (
+        ip TEXT PRIMARY KEY,
+        client TEXT NOT NULL,
+        status TEXT NOT NULL,
+        issued INTEGER,
+        expires INTEGER,
+        expired INTEGER,
+        seen INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS leases_client ON leases (client);
+    CREATE TABLE IF NOT EXISTS history (
+        client TEXT PRIMARY KEY,
+        ip TEXT NOT NULL
+    );
+";
+
+const SELECT_LEASE: &str = "SELECT ip, client, status, issued, expires, expired, seen FROM leases";
+
+/// Dzierżawy w bazie SQLite, każda zmiana od razu trafia do pliku
+pub struct SqliteStore {
+    conn: Connection,
+    history_size: i64,
+}
+
+fn status_name(status: Status) -> &'static str {
+    match status {
+        Status::Offered => "offered",
+        Status::Leasing => "leasing",
+        Status::Reserved => "reserved",
+        Status::Expiring => "expiring",
+        Status::Declined => "declined",
+        Status::Bootp => "bootp",
+    }
+}
+
+fn status_from_name(name: &str) -> Option<Status> {
+    match name {
+        "offered" => Some(Status::Offered),
+        "leasing" => Some(Status::Leasing),
+        "reserved" => Some(Status::Reserved),
+        "expiring" => Some(Status::Expiring),
+        "declined" => Some(Status::Declined),
+        "bootp" => Some(Status::Bootp),
+        _ => None,
+    }
+}
+
+fn db_time(time: Option<SystemTime>) -> Option<i64> {
+    time.map(|time| unix_secs(time) as i64)
+}
+
+/// Błąd bazy jako błąd zapisu magazynu
+fn write_error(e: rusqlite::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+/// Wynik odczytu z bazy. Zablokowana albo uszkodzona baza nie zatrzymuje serwera - zgłaszamy błąd
+/// i odpowiadamy tak, jakby wpisów nie było.
+fn read_or_default<T: Default>(result: rusqlite::Result<T>) -> T {
+    result.unwrap_or_else(|e| {
+        println!("Couldn't read lease database: {}", e);
+        T::default()
+    })
+}
+
+fn ip_text(ip: u32) -> String {
+    Ipv4Addr::from(ip).to_string()
+}
+
+fn ip_from_text(text: &str) -> Option<u32> {
+    text.parse::<Ipv4Addr>().ok().map(u32::from)
+}
+
+/// Wiersz tabeli leases. Wiersze, których nie rozumiemy (np. poprawione ręcznie), pomijamy.
+fn row_lease(row: &Row) -> rusqlite::Result<Option<Lease>> {
+    let ip: String = row.get(0)?;
+    let client: String = row.get(1)?;
+    let status: String = row.get(2)?;
+    let times: Vec<Option<i64>> = vec![row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?];
+    let time = |i: usize| times[i].map(|secs| from_unix_secs(secs as u64));
+
+    Ok(match (ip_from_text(&ip), client.parse::<ClientId>(), status_from_name(&status)) {
+        (Some(ip), Ok(client), Some(status)) => Some(Lease {
+            ip: ip,
+            client: client,
+            status: status,
+            issued_at: time(0),
+            expires_at: time(1),
+            expired_at: time(2),
+            last_seen: time(3),
+        }),
+        _ => {
+            println!("Skipping unreadable lease row for {}", ip);
+            None
+        },
+    })
+}
+
+impl SqliteStore {
+    /// Otwarcie bazy, tabele zakładamy przy pierwszym uruchomieniu
+    pub fn open(path: &str, history_size: usize) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteStore { conn: conn, history_size: history_size as i64 })
+    }
+
+    /// Wpisy spełniające warunek zapytania
+    fn select(&self, sql: &str, params: &[&dyn rusqlite::ToSql]) -> rusqlite::Result<Vec<Lease>> {
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let rows = stmt.query_map(params, row_lease)?;
+        let mut leases = Vec::new();
+        for row in rows {
+            leases.extend(row?);
+        }
+        Ok(leases)
+    }
+
+    fn select_where(&self, condition: &str, param: &str) -> Vec<Lease> {
+        read_or_default(self.select(&format!("{} {}", SELECT_LEASE, condition), params![param]))
+    }
+}
+
+impl LeaseStore for SqliteStore {
+    fn get(&self, ip: u32) -> Option<Lease> {
+        self.select_where("WHERE ip = ?1", &ip_text(ip)).pop()
+    }
+
+    fn by_client(&self, client: &ClientId) -> Vec<Lease> {
+        self.select_where("WHERE client = ?1", &client.to_string())
+    }
+
+    fn insert(&mut self, lease: Lease) -> io::Result<Option<Lease>> {
+        let prev_lease = self.get(lease.ip);
+        self.conn.execute(
+            "INSERT OR REPLACE INTO leases (ip, client, status, issued, expires, expired, seen) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                ip_text(lease.ip),
+                lease.client.to_string(),
+                status_name(lease.status),
+                db_time(lease.issued_at),
+                db_time(lease.expires_at),
+                db_time(lease.expired_at),
+                db_time(lease.last_seen),
+            ],
+        ).map_err(write_error)?;
+        Ok(prev_lease)
+    }
+
+    fn update(&mut self, lease: Lease) -> io::Result<bool> {
+        let changed = self.conn.execute(
+            "UPDATE leases SET client = ?2, status = ?3, issued = ?4, expires = ?5, expired = ?6, seen = ?7 WHERE ip = ?1",
+            params![
+                ip_text(lease.ip),
+                lease.client.to_string(),
+                status_name(lease.status),
+                db_time(lease.issued_at),
+                db_time(lease.expires_at),
+                db_time(lease.expired_at),
+                db_time(lease.last_seen),
+            ],
+        ).map_err(write_error)?;
+        Ok(changed > 0)
+    }
+
+    fn expire(&mut self, ip: u32) -> io::Result<Option<Lease>> {
+        let lease = self.get(ip);
+        if lease.is_some() {
+            self.conn.execute("DELETE FROM leases WHERE ip = ?1", params![ip_text(ip)]).map_err(write_error)?;
+        }
+        Ok(lease)
+    }
+
+    fn leases(&self) -> Vec<Lease> {
+        read_or_default(self.select(SELECT_LEASE, params![]))
+    }
+
+    fn last_address(&self, client: &ClientId) -> Option<u32> {
+        let ip: Option<String> = read_or_default(self.conn
+            .query_row("SELECT ip FROM history WHERE client = ?1", params![client.to_string()], |row| row.get(0))
+            .optional());
+        ip.and_then(|ip| ip_from_text(&ip))
+    }
+
+    fn remember(&mut self, client: &ClientId, ip: u32) -> io::Result<()> {
+        self.conn.execute("INSERT OR REPLACE INTO history (client, ip) VALUES (?1, ?2)", params![client.to_string(), ip_text(ip)])
+            .map_err(write_error)?;
+        // REPLACE usuwa stary wiersz klienta i wstawia nowy z kolejnym rowid, więc rowid rośnie z czasem ostatniej obsługi
+        self.conn.execute(
+            "DELETE FROM history WHERE rowid <= (SELECT rowid FROM history ORDER BY rowid DESC LIMIT 1 OFFSET ?1)",
+            params![self.history_size],
+        ).map_err(write_error)?;
+        Ok(())
+    }
+}